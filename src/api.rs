@@ -0,0 +1,212 @@
+use serde_json::{Map, Value};
+use urlencoding::encode;
+
+use crate::{
+    book::{BookDetails, LibgenBook, Section},
+    identifiers::{Isbn, LibgenId},
+    util::parse_authors,
+};
+
+/// The fields requested from `json.php`
+pub const JSON_FIELDS: [&str; 19] = [
+    "id",
+    "title",
+    "author",
+    "publisher",
+    "year",
+    "series",
+    "edition",
+    "language",
+    "pages",
+    "identifier",
+    "extension",
+    "filesize",
+    "md5",
+    "sha1",
+    "sha256",
+    "tth",
+    "btih",
+    "timeadded",
+    "timelastmodified",
+];
+
+/// How many ids go into one `json.php` request
+pub const JSON_BATCH_SIZE: usize = 100;
+
+/// A book as described by the JSON API, with what search pages leave out
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookMetadata {
+    /// The book, as a search would have found it
+    pub book: LibgenBook,
+    /// Exact size and hashes
+    pub details: BookDetails,
+    /// Language of the book
+    pub language: Option<String>,
+    /// Page count, as entered by librarians
+    pub pages: Option<String>,
+    /// Edition
+    pub edition: Option<String>,
+    /// When the file was added, e.g. `2014-03-09 21:31:51`
+    pub time_added: Option<String>,
+    /// When the record was last changed
+    pub time_last_modified: Option<String>,
+}
+
+impl BookMetadata {
+    /// Maps one record of a `json.php` response
+    ///
+    /// Libgen sends every value as a string, numbers are accepted as well. Returns
+    /// `None` without a valid id and md5.
+    pub fn from_json(record: &Map<String, Value>) -> Option<BookMetadata> {
+        let field = |name: &str| {
+            match record.get(name)? {
+                Value::String(value) => Some(value.trim().to_owned()),
+                Value::Number(value) => Some(value.to_string()),
+                _ => None,
+            }
+            .filter(|value| !value.is_empty())
+        };
+
+        let book = LibgenBook {
+            libgen_id: Some(field("id")?.parse::<LibgenId>().ok()?),
+            title: field("title").unwrap_or_default(),
+            authors: parse_authors(&field("author").unwrap_or_default()),
+            publisher: field("publisher").unwrap_or_default(),
+            libgen_md5: field("md5")?.parse().ok()?,
+            file_type: field("extension").unwrap_or_default(),
+            isbns: field("identifier")
+                .unwrap_or_default()
+                .split([',', ';'])
+                .filter_map(|isbn| isbn.parse::<Isbn>().ok())
+                .collect(),
+            year: field("year").and_then(|year| year.parse().ok()),
+            series: field("series"),
+            section: Section::NonFiction,
+        };
+
+        Some(BookMetadata {
+            book,
+            details: BookDetails {
+                size_bytes: field("filesize").and_then(|size| size.parse().ok()),
+                btih: field("btih"),
+                tth: field("tth"),
+                sha1: field("sha1"),
+                sha256: field("sha256"),
+            },
+            language: field("language"),
+            pages: field("pages"),
+            edition: field("edition"),
+            time_added: field("timeadded"),
+            time_last_modified: field("timelastmodified"),
+        })
+    }
+
+    /// Fills in what a search result is missing, e.g. ISBNs, year and series
+    ///
+    /// Nothing is changed unless both describe the same file.
+    pub fn enrich(&self, book: &mut LibgenBook) {
+        if book.libgen_md5 != self.book.libgen_md5 {
+            return;
+        }
+        if book.libgen_id.is_none() {
+            book.libgen_id = self.book.libgen_id;
+        }
+        if book.authors.is_empty() {
+            book.authors = self.book.authors.clone();
+        }
+        if book.publisher.is_empty() {
+            book.publisher = self.book.publisher.clone();
+        }
+        if book.isbns.is_empty() {
+            book.isbns = self.book.isbns.clone();
+        }
+        if book.file_type.is_empty() {
+            book.file_type = self.book.file_type.clone();
+        }
+        book.year = book.year.or(self.book.year);
+        if book.series.is_none() {
+            book.series = self.book.series.clone();
+        }
+    }
+}
+
+/// Path and query asking `json.php` about `ids`
+pub fn api_path(ids: &[u64]) -> String {
+    let ids: Vec<String> = ids.iter().map(u64::to_string).collect();
+    format!(
+        "json.php?ids={}&fields={}",
+        encode(&ids.join(",")),
+        JSON_FIELDS.join(",")
+    )
+}
+
+/// Maps a `json.php` response, skipping records without an id or md5
+pub fn parse_api_response(body: &str) -> Result<Vec<BookMetadata>, serde_json::Error> {
+    let records: Vec<Map<String, Value>> = serde_json::from_str(body)?;
+    Ok(records.iter().filter_map(BookMetadata::from_json).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{book::Author, downloader::tests::test_client};
+
+    const RESPONSE: &str = r#"[{"id":"3750","title":"Abstract and concrete categories: the joy of cats",
+        "author":"Jiri Adamek; Horst Herrlich","publisher":"Wiley-Interscience","year":"1990",
+        "series":"","language":"English","pages":"482","identifier":"0471609226,9780471609223",
+        "extension":"pdf","filesize":"3076236","md5":"5FA82BE26689A4E6F4415EA068D35A9D",
+        "btih":"2ddc1a0b3ab6bb8ebe2f9a7f5b3e5b4e4e4c1b6f","timeadded":"2014-03-09 21:31:51"},
+        {"id":"","md5":"broken"}]"#;
+
+    #[test]
+    fn parse_json_records() {
+        let books = parse_api_response(RESPONSE).unwrap();
+        assert_eq!(books.len(), 1);
+
+        let metadata = &books[0];
+        assert_eq!(metadata.book.libgen_id, Some(LibgenId(3750)));
+        assert_eq!(
+            metadata.book.authors,
+            vec![Author::new("Jiri Adamek"), Author::new("Horst Herrlich")]
+        );
+        assert_eq!(metadata.book.year, Some(1990));
+        assert_eq!(metadata.book.series, None);
+        assert_eq!(metadata.book.isbns.len(), 2);
+        assert_eq!(metadata.details.size_bytes, Some(3076236));
+        assert_eq!(metadata.language.as_deref(), Some("English"));
+        assert_eq!(metadata.time_added.as_deref(), Some("2014-03-09 21:31:51"));
+    }
+
+    #[test]
+    fn enrich_fills_gaps_only() {
+        let metadata = parse_api_response(RESPONSE).unwrap().remove(0);
+        let mut book = crate::downloader::tests::book_with_md5("5fa82be26689a4e6f4415ea068d35a9d");
+        book.publisher = "Wiley".to_owned();
+
+        metadata.enrich(&mut book);
+        assert_eq!(book.year, Some(1990));
+        assert_eq!(book.isbns.len(), 2);
+        assert_eq!(book.publisher, "Wiley");
+
+        let mut other = crate::downloader::tests::book_with_md5("5eb63bbbe01eeed093cb22bb8f5acdc3");
+        metadata.enrich(&mut other);
+        assert_eq!(other.year, None);
+    }
+
+    #[tokio::test]
+    async fn client_fetches_by_ids() {
+        let host = crate::downloader::tests::serve_bytes(RESPONSE.as_bytes()).await;
+        let client = test_client(&host).build().unwrap();
+
+        let mut books = vec![crate::downloader::tests::book_with_md5(
+            "5fa82be26689a4e6f4415ea068d35a9d",
+        )];
+        client.enrich_books(&mut books).await.unwrap();
+        assert_eq!(books[0].year, Some(1990));
+    }
+
+    #[test]
+    fn api_path_lists_ids_and_fields() {
+        assert!(api_path(&[1, 2]).starts_with("json.php?ids=1%2C2&fields=id,title,"));
+    }
+}
@@ -0,0 +1,163 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use tokio::time::{sleep, Duration, Instant};
+
+/// Stands in for "no limit" in the shared rate
+const UNLIMITED: u64 = u64::MAX;
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// A token bucket limiting how many bytes per second pass through it.
+///
+/// Clones share the same bucket, so one limiter can cap several downloads at
+/// once. The rate can be changed at any time and applies to the next chunk.
+#[derive(Debug, Clone)]
+pub struct BandwidthLimiter {
+    /// Bytes per second, [`UNLIMITED`] when there is no limit
+    rate: Arc<AtomicU64>,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl Default for BandwidthLimiter {
+    fn default() -> Self {
+        BandwidthLimiter::new(None)
+    }
+}
+
+impl BandwidthLimiter {
+    /// Creates a limiter, `None` lets everything through
+    pub fn new(bytes_per_second: Option<u64>) -> BandwidthLimiter {
+        BandwidthLimiter::with_rate(Arc::new(AtomicU64::new(
+            bytes_per_second.unwrap_or(UNLIMITED),
+        )))
+    }
+
+    fn with_rate(rate: Arc<AtomicU64>) -> BandwidthLimiter {
+        let tokens = rate.load(Ordering::Relaxed) as f64;
+        BandwidthLimiter {
+            rate,
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens,
+                last_refill: Instant::now(),
+            })),
+        }
+    }
+
+    /// A new, separate bucket that follows this limiters rate
+    ///
+    /// Used to give each download its own cap that can still be adjusted in one place.
+    pub fn independent_bucket(&self) -> BandwidthLimiter {
+        BandwidthLimiter::with_rate(self.rate.clone())
+    }
+
+    /// The current limit in bytes per second
+    pub fn rate(&self) -> Option<u64> {
+        match self.rate.load(Ordering::Relaxed) {
+            UNLIMITED => None,
+            rate => Some(rate),
+        }
+    }
+
+    /// Changes the limit, `None` removes it
+    ///
+    /// A limit of `0` is not "no limit", it lets through one byte per second.
+    pub fn set_rate(&self, bytes_per_second: Option<u64>) {
+        self.rate
+            .store(bytes_per_second.unwrap_or(UNLIMITED), Ordering::Relaxed);
+    }
+
+    /// Waits until `bytes` may be sent
+    ///
+    /// The bucket holds at most one second worth of bytes. Requests larger than
+    /// what is available go into debt and wait for it to be paid back.
+    pub async fn acquire(&self, bytes: u64) {
+        let Some(rate) = self.rate() else {
+            return;
+        };
+        let rate = rate.max(1) as f64;
+
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(rate);
+            bucket.last_refill = now;
+            bucket.tokens -= bytes as f64;
+            if bucket.tokens < 0.0 {
+                Some(Duration::from_secs_f64(-bucket.tokens / rate))
+            } else {
+                None
+            }
+        };
+
+        if let Some(wait) = wait {
+            sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn limits_to_rate() {
+        let limiter = BandwidthLimiter::new(Some(100));
+        let start = Instant::now();
+
+        // The first second is already in the bucket
+        limiter.acquire(100).await;
+        assert!(start.elapsed() < Duration::from_millis(10));
+
+        limiter.acquire(200).await;
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_secs(2) && elapsed < Duration::from_millis(2100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_changes_at_runtime() {
+        let limiter = BandwidthLimiter::new(Some(10));
+        let shared = limiter.clone();
+        let start = Instant::now();
+
+        shared.set_rate(None);
+        limiter.acquire(1_000_000).await;
+        assert!(start.elapsed() < Duration::from_millis(10));
+        assert_eq!(limiter.rate(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn independent_buckets_share_rate() {
+        let global = BandwidthLimiter::new(Some(100));
+        let first = global.independent_bucket();
+        let second = global.independent_bucket();
+        let start = Instant::now();
+
+        // Each bucket has its own second of burst
+        first.acquire(100).await;
+        second.acquire(100).await;
+        assert!(start.elapsed() < Duration::from_millis(10));
+
+        global.set_rate(Some(50));
+        assert_eq!(first.rate(), Some(50));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn zero_is_not_unlimited() {
+        let limiter = BandwidthLimiter::new(Some(0));
+        assert_eq!(limiter.rate(), Some(0));
+        let start = Instant::now();
+        limiter.acquire(2).await;
+        assert!(start.elapsed() >= Duration::from_secs(2));
+
+        limiter.set_rate(None);
+        limiter.set_rate(Some(0));
+        assert_eq!(limiter.rate(), Some(0));
+    }
+}
@@ -1,55 +1,210 @@
+use serde::{Deserialize, Serialize};
 use urlencoding::encode;
 
-use crate::util::calculate_group_id;
+use crate::{
+    filename::FilenameTemplate,
+    identifiers::{Isbn, LibgenId, Md5},
+};
 
-#[derive(Debug, PartialEq)]
+/// The part an author played in creating a book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuthorRole {
+    /// Marked as `(auth.)`
+    Author,
+    /// Marked as `(ed.)` or `(eds.)`
+    Editor,
+    /// Marked as `(trans.)` or `(transl.)`
+    Translator,
+    /// Marked as `(ill.)`
+    Illustrator,
+    /// Marked as `(comp.)`
+    Compiler,
+}
+
+impl AuthorRole {
+    /// Matches the contents of a role suffix, without the brackets.
+    pub fn from_suffix(suffix: &str) -> Option<AuthorRole> {
+        match suffix
+            .trim()
+            .trim_end_matches('.')
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "auth" | "author" => Some(AuthorRole::Author),
+            "ed" | "eds" | "editor" | "editors" => Some(AuthorRole::Editor),
+            "trans" | "transl" | "tr" | "translator" => Some(AuthorRole::Translator),
+            "ill" | "illus" | "illustrator" => Some(AuthorRole::Illustrator),
+            "comp" | "compiler" => Some(AuthorRole::Compiler),
+            _ => None,
+        }
+    }
+}
+
+/// The libgen collection a book belongs to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Section {
+    /// The main collection searched through `search.php`
+    #[default]
+    NonFiction,
+    /// The fiction collection
+    Fiction,
+    /// Scientific articles, see [`crate::scimag::LibgenArticle`]
+    Scimag,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[doc = r" A person credited on a book."]
+pub struct Author {
+    /// The authors name, trimmed and without a role suffix
+    pub name: String,
+    /// The role from the suffix libgen attaches, if it was recognised
+    pub role: Option<AuthorRole>,
+}
+
+impl Author {
+    /// Creates an author without a role
+    pub fn new(name: &str) -> Author {
+        Author {
+            name: name.to_owned(),
+            role: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[doc = r" The data collected from a search result."]
 pub struct LibgenBook {
-    /// The books id on libgen
-    pub libgen_id: u64,
+    /// The books id on libgen, `None` where it isn't listed, e.g. for fiction search results
+    pub libgen_id: Option<LibgenId>,
     /// Books title
     pub title: String,
     /// Authors who made the book
-    pub authors: Vec<String>,
+    pub authors: Vec<Author>,
     /// The publisher of the book (some books have multiple which is not supported)
     pub publisher: String,
     /// The direct download link for the book
-    pub libgen_md5: String,
+    pub libgen_md5: Md5,
     /// File type
     pub file_type: String,
+    /// ISBNs listed under the title
+    pub isbns: Vec<Isbn>,
+    /// Year of publication
+    pub year: Option<u16>,
+    /// The series the book is part of
+    pub series: Option<String>,
+    /// The collection the book was found in
+    #[serde(default)]
+    pub section: Section,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[doc = r" Extra data only shown on a books detail page."]
+pub struct BookDetails {
+    /// Exact file size in bytes
+    pub size_bytes: Option<u64>,
+    /// BitTorrent info hash of the single file torrent
+    pub btih: Option<String>,
+    /// Tiger tree hash, used by DC++ style clients
+    pub tth: Option<String>,
+    /// SHA1 of the file
+    pub sha1: Option<String>,
+    /// SHA256 of the file
+    pub sha256: Option<String>,
 }
 
 impl LibgenBook {
+    /// Builds a magnet link from the hashes on the books detail page
+    ///
+    /// The display name is the file name `template` would give the book. Returns
+    /// `None` when the page listed neither a BTIH nor a TTH.
+    pub fn magnet_uri(&self, details: &BookDetails, template: &FilenameTemplate) -> Option<String> {
+        let mut topics: Vec<String> = Vec::new();
+        if let Some(btih) = &details.btih {
+            topics.push(format!("urn:btih:{}", btih));
+        }
+        if let Some(tth) = &details.tth {
+            topics.push(format!("urn:tree:tiger:{}", tth));
+        }
+
+        let mut magnet = match topics.as_slice() {
+            [] => return None,
+            [topic] => format!("magnet:?xt={}", topic),
+            // Several exact topics have to be numbered
+            _ => format!(
+                "magnet:?{}",
+                topics
+                    .iter()
+                    .enumerate()
+                    .map(|(i, topic)| format!("xt.{}={}", i + 1, topic))
+                    .collect::<Vec<_>>()
+                    .join("&")
+            ),
+        };
+        if let Some(size) = details.size_bytes {
+            magnet.push_str(&format!("&xl={}", size));
+        }
+        let path = template.render(self);
+        if let Some(name) = path.file_name() {
+            magnet.push_str(&format!("&dn={}", encode(&name.to_string_lossy())));
+        }
+        Some(magnet)
+    }
+
     #[doc = r"Build the books download link."]
+    ///
+    /// Fiction search results don't list the books id, which the link needs. For
+    /// those this fails until the id is known, the [`crate::downloader::Downloader`]
+    /// reads their link from the download page instead.
     pub fn build_direct_download_url(&self) -> Result<String, String> {
         // TODO: URL hardcoding?
-        Ok(format!(
-            "https://download.library.lol/main/{}/{}/{}.{}",
-            calculate_group_id(self.libgen_id),
-            self.libgen_md5,
-            encode(&self.title),
-            self.file_type
-        ))
+        match (self.section, self.libgen_id) {
+            (Section::NonFiction, Some(id)) => Ok(format!(
+                "https://download.library.lol/main/{}/{}/{}.{}",
+                id.group_id(),
+                self.libgen_md5,
+                encode(&self.title),
+                self.file_type
+            )),
+            (Section::Scimag, _) => Err(format!(
+                "Article {} is downloaded by its DOI",
+                self.libgen_md5
+            )),
+            // Fiction files keep their extension in the directory name
+            (Section::Fiction, Some(id)) => Ok(format!(
+                "https://download.library.lol/fiction/{}/{}.{}/{}.{}",
+                id.group_id(),
+                self.libgen_md5,
+                self.file_type,
+                encode(&self.title),
+                self.file_type
+            )),
+            (_, None) => Err(format!("Libgen id of {} is unknown", self.libgen_md5)),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::LibgenBook;
+    use super::{Author, AuthorRole, BookDetails, LibgenBook, Section};
+    use crate::{filename::FilenameTemplate, identifiers::LibgenId};
 
     #[test]
     fn build_direct_download_url() {
         let valid_cat_result = LibgenBook {
-            libgen_id: 3750,
-            libgen_md5: "5fa82be26689a4e6f4415ea068d35a9d".to_owned(),
+            libgen_id: Some(LibgenId(3750)),
+            libgen_md5: "5fa82be26689a4e6f4415ea068d35a9d".parse().unwrap(),
             file_type: "pdf".to_owned(),
             title: "Abstract and concrete categories: the joy of cats".to_owned(),
             authors: vec![
-                "Jiri Adamek".to_string(),
-                " Horst Herrlich".to_string(),
-                " George E. Strecker".to_string(),
+                Author::new("Jiri Adamek"),
+                Author::new("Horst Herrlich"),
+                Author::new("George E. Strecker"),
             ],
             publisher: "Wiley-Interscience".to_owned(),
+            isbns: vec![],
+            year: Some(1990),
+            series: None,
+            section: Section::NonFiction,
         };
 
         let valid_download_link = "https://download.library.lol/main/3000/5fa82be26689a4e6f4415ea068d35a9d/Abstract%20and%20concrete%20categories%3A%20the%20joy%20of%20cats.pdf";
@@ -57,4 +212,76 @@ mod tests {
         let download_link = valid_cat_result.build_direct_download_url();
         assert_eq!(valid_download_link, download_link.unwrap());
     }
+
+    #[test]
+    fn build_fiction_download_url() {
+        let mut novel = LibgenBook {
+            libgen_id: None,
+            libgen_md5: "2b0a2e0b4f1c4a0e9a3d6c1e8f7b5a41".parse().unwrap(),
+            file_type: "epub".to_owned(),
+            title: "The Left Hand of Darkness".to_owned(),
+            authors: vec![Author::new("Ursula K. Le Guin")],
+            publisher: String::new(),
+            isbns: vec![],
+            year: None,
+            series: Some("Hainish Cycle".to_owned()),
+            section: Section::Fiction,
+        };
+        assert!(novel.build_direct_download_url().is_err());
+
+        novel.libgen_id = Some(LibgenId(2185123));
+        assert_eq!(
+            novel.build_direct_download_url().unwrap(),
+            "https://download.library.lol/fiction/2185000/2b0a2e0b4f1c4a0e9a3d6c1e8f7b5a41.epub/The%20Left%20Hand%20of%20Darkness.epub"
+        );
+    }
+
+    #[test]
+    fn author_role_from_suffix() {
+        assert_eq!(AuthorRole::from_suffix("auth."), Some(AuthorRole::Author));
+        assert_eq!(AuthorRole::from_suffix("Eds."), Some(AuthorRole::Editor));
+        assert_eq!(
+            AuthorRole::from_suffix("transl."),
+            Some(AuthorRole::Translator)
+        );
+        assert_eq!(AuthorRole::from_suffix("Jr"), None);
+    }
+
+    #[test]
+    fn magnet_uri_from_details() {
+        let book = LibgenBook {
+            libgen_id: Some(LibgenId(3750)),
+            libgen_md5: "5fa82be26689a4e6f4415ea068d35a9d".parse().unwrap(),
+            file_type: "pdf".to_owned(),
+            title: "Abstract and concrete categories: the joy of cats".to_owned(),
+            authors: vec![Author::new("Jiri Adamek")],
+            publisher: "Wiley-Interscience".to_owned(),
+            isbns: vec![],
+            year: Some(1990),
+            series: None,
+            section: Section::NonFiction,
+        };
+        let template = FilenameTemplate::new("{author} - {title}.{ext}").unwrap();
+
+        assert_eq!(book.magnet_uri(&BookDetails::default(), &template), None);
+
+        let details = BookDetails {
+            size_bytes: Some(3076236),
+            btih: Some("2ddc1a0b3ab6bb8ebe2f9a7f5b3e5b4e4e4c1b6f".to_owned()),
+            ..BookDetails::default()
+        };
+        assert_eq!(
+            book.magnet_uri(&details, &template).unwrap(),
+            "magnet:?xt=urn:btih:2ddc1a0b3ab6bb8ebe2f9a7f5b3e5b4e4e4c1b6f&xl=3076236&dn=Jiri%20Adamek%20-%20Abstract%20and%20concrete%20categories_%20the%20joy%20of%20cats.pdf"
+        );
+
+        let details = BookDetails {
+            tth: Some("QWERTYUIOPASDFGHJKLZXCVBNM234567QWERTYU".to_owned()),
+            ..details
+        };
+        assert!(book
+            .magnet_uri(&details, &template)
+            .unwrap()
+            .starts_with("magnet:?xt.1=urn:btih:2ddc1a0b3ab6bb8ebe2f9a7f5b3e5b4e4e4c1b6f&xt.2=urn:tree:tiger:QWERTY"));
+    }
 }
@@ -0,0 +1,357 @@
+use md5::{Digest, Md5 as Md5Hasher};
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::OsStr,
+    path::PathBuf,
+    time::{Duration, UNIX_EPOCH},
+};
+use tokio::fs;
+use url::Url;
+
+use crate::{queue::write_atomically, util::unix_now};
+
+/// Whether the network is used at all
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Serve fresh entries from the cache and fetch everything else
+    #[default]
+    ReadWrite,
+    /// Never go to the network, serving cached pages however old they are
+    Offline,
+}
+
+/// How a single request uses the cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// Always fetched and never stored, for pages that are only useful fresh
+    Bypass,
+    /// Search results, which change as books are added, served for [`CacheConfig::search_ttl`]
+    Search,
+    /// Detail pages and metadata, served for [`CacheConfig::ttl`]
+    Page,
+}
+
+/// Where and for how long pages are cached
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Directory the cache lives in, pages are kept one file each in its `pages` subdirectory
+    pub directory: PathBuf,
+    /// How long a cached page is served before it is fetched again
+    pub ttl: Duration,
+    /// Like `ttl`, for search results
+    pub search_ttl: Duration,
+    /// Size of the cache directory, the oldest pages are removed past it
+    pub max_bytes: u64,
+    /// Whether misses go to the network
+    pub mode: CacheMode,
+}
+
+impl CacheConfig {
+    /// Caches pages in `directory` for a day and searches for 15 minutes, up to 100 MiB
+    pub fn new(directory: impl Into<PathBuf>) -> CacheConfig {
+        CacheConfig {
+            directory: directory.into(),
+            ttl: Duration::from_secs(24 * 60 * 60),
+            search_ttl: Duration::from_secs(15 * 60),
+            max_bytes: 100 * 1024 * 1024,
+            mode: CacheMode::ReadWrite,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    url: String,
+    /// Seconds since the unix epoch
+    fetched_at: u64,
+    body: String,
+}
+
+/// Search and detail pages kept on disk, keyed by their normalized URL
+///
+/// Failing to read or write the cache is never an error, the page is just
+/// fetched again.
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    config: CacheConfig,
+}
+
+impl ResponseCache {
+    /// Creates a cache, the directory is created on the first write
+    pub fn new(config: CacheConfig) -> ResponseCache {
+        ResponseCache { config }
+    }
+
+    /// Its configuration
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    /// Whether misses may go to the network
+    pub fn is_offline(&self) -> bool {
+        self.config.mode == CacheMode::Offline
+    }
+
+    /// The cached body for `url`, unless it has expired under `policy`
+    ///
+    /// In [`CacheMode::Offline`] expired pages are returned too.
+    pub async fn get(&self, url: &str, policy: CachePolicy) -> Option<String> {
+        let ttl = match policy {
+            CachePolicy::Bypass => return None,
+            CachePolicy::Search => self.config.search_ttl,
+            CachePolicy::Page => self.config.ttl,
+        };
+        let key = normalize_url(url);
+        let entry: CacheEntry =
+            serde_json::from_slice(&fs::read(self.entry_path(&key)).await.ok()?).ok()?;
+        // Guards against hash collisions
+        if entry.url != key {
+            return None;
+        }
+        let age = unix_now().saturating_sub(entry.fetched_at);
+        if self.is_offline() || age < ttl.as_secs() {
+            Some(entry.body)
+        } else {
+            None
+        }
+    }
+
+    /// Stores the body of `url`, then trims the cache to its size limit
+    pub async fn put(&self, url: &str, body: &str) {
+        if body.len() as u64 > self.config.max_bytes {
+            return;
+        }
+        let key = normalize_url(url);
+        let entry = CacheEntry {
+            url: key.clone(),
+            fetched_at: unix_now(),
+            body: body.to_owned(),
+        };
+        let Ok(bytes) = serde_json::to_vec(&entry) else {
+            return;
+        };
+        if fs::create_dir_all(self.pages_directory()).await.is_err()
+            || write_atomically(&self.entry_path(&key), &bytes)
+                .await
+                .is_err()
+        {
+            return;
+        }
+        self.evict().await;
+    }
+
+    /// Forgets the page cached for `url`, if any
+    pub async fn remove(&self, url: &str) {
+        let _ = fs::remove_file(self.entry_path(&normalize_url(url))).await;
+    }
+
+    /// Removes every cached page, leaving any other file alone
+    pub async fn clear(&self) -> std::io::Result<()> {
+        let mut directory = match fs::read_dir(self.pages_directory()).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            result => result?,
+        };
+        while let Some(entry) = directory.next_entry().await? {
+            if is_entry_name(&entry.file_name()) {
+                fs::remove_file(entry.path()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes the least recently written pages until the cache fits `max_bytes`
+    async fn evict(&self) {
+        let Ok(mut directory) = fs::read_dir(self.pages_directory()).await else {
+            return;
+        };
+        let mut entries = Vec::new();
+        while let Ok(Some(entry)) = directory.next_entry().await {
+            if is_entry_name(&entry.file_name()) {
+                if let Ok(metadata) = entry.metadata().await {
+                    let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+                    entries.push((modified, metadata.len(), entry.path()));
+                }
+            }
+        }
+
+        let mut total: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        entries.sort();
+        for (_, len, path) in entries {
+            if total <= self.config.max_bytes {
+                break;
+            }
+            if fs::remove_file(&path).await.is_ok() {
+                total -= len;
+            }
+        }
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        let digest = Md5Hasher::digest(key.as_bytes());
+        let name: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+        self.pages_directory().join(format!("{}.json", name))
+    }
+
+    fn pages_directory(&self) -> PathBuf {
+        self.config.directory.join("pages")
+    }
+}
+
+/// Whether `name` is one of ours, the hex md5 of a URL followed by `.json`
+fn is_entry_name(name: &OsStr) -> bool {
+    name.to_str()
+        .and_then(|name| name.strip_suffix(".json"))
+        .is_some_and(|digest| {
+            digest.len() == 32
+                && digest
+                    .bytes()
+                    .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+        })
+}
+
+/// Lowercases scheme and host, sorts the query and drops the fragment
+///
+/// Empty query parameters are left out, so `search.php?&req=cats` and
+/// `search.php?req=cats` share an entry.
+pub fn normalize_url(url: &str) -> String {
+    let Ok(mut parsed) = Url::parse(url) else {
+        return url.to_owned();
+    };
+    parsed.set_fragment(None);
+    let mut pairs: Vec<(String, String)> = parsed
+        .query_pairs()
+        .filter(|(name, _)| !name.is_empty())
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    pairs.sort();
+    if pairs.is_empty() {
+        parsed.set_query(None);
+    } else {
+        parsed.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    parsed.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_urls() {
+        assert_eq!(
+            normalize_url("HTTPS://www.LibGen.is/search.php?&req=cats&column=title#top"),
+            normalize_url("https://www.libgen.is/search.php?column=title&req=cats")
+        );
+        assert_ne!(
+            normalize_url("https://www.libgen.is/search.php?req=cats"),
+            normalize_url("https://www.libgen.rs/search.php?req=cats")
+        );
+    }
+
+    #[tokio::test]
+    async fn serves_until_expired() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut config = CacheConfig::new(directory.path().join("cache"));
+        let cache = ResponseCache::new(config.clone());
+
+        assert_eq!(
+            cache
+                .get("http://libgen.invalid/a", CachePolicy::Page)
+                .await,
+            None
+        );
+        cache.put("http://libgen.invalid/a", "page").await;
+        assert_eq!(
+            cache
+                .get("http://libgen.invalid/a", CachePolicy::Page)
+                .await
+                .as_deref(),
+            Some("page")
+        );
+
+        assert_eq!(
+            cache
+                .get("http://libgen.invalid/a", CachePolicy::Bypass)
+                .await,
+            None
+        );
+
+        config.search_ttl = Duration::ZERO;
+        let searches_expired = ResponseCache::new(config.clone());
+        assert_eq!(
+            searches_expired
+                .get("http://libgen.invalid/a", CachePolicy::Search)
+                .await,
+            None
+        );
+        assert!(searches_expired
+            .get("http://libgen.invalid/a", CachePolicy::Page)
+            .await
+            .is_some());
+
+        config.ttl = Duration::ZERO;
+        assert_eq!(
+            ResponseCache::new(config.clone())
+                .get("http://libgen.invalid/a", CachePolicy::Page)
+                .await,
+            None
+        );
+        config.mode = CacheMode::Offline;
+        assert!(ResponseCache::new(config)
+            .get("http://libgen.invalid/a", CachePolicy::Page)
+            .await
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn stays_under_its_size_limit() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = ResponseCache::new(CacheConfig {
+            max_bytes: 200,
+            ..CacheConfig::new(directory.path())
+        });
+
+        for page in ["a", "b", "c", "d"] {
+            cache
+                .put(&format!("http://libgen.invalid/{}", page), &"x".repeat(60))
+                .await;
+        }
+        assert!(cache
+            .get("http://libgen.invalid/d", CachePolicy::Page)
+            .await
+            .is_some());
+        assert!(cache
+            .get("http://libgen.invalid/a", CachePolicy::Page)
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn leaves_other_files_alone() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = ResponseCache::new(CacheConfig {
+            max_bytes: 100,
+            ..CacheConfig::new(directory.path())
+        });
+        let pages = directory.path().join("pages");
+        std::fs::create_dir_all(&pages).unwrap();
+        for name in ["notes.json", "old.txt"] {
+            std::fs::write(pages.join(name), "x".repeat(200)).unwrap();
+        }
+        std::fs::write(directory.path().join("settings.json"), "{}").unwrap();
+
+        cache.put("http://libgen.invalid/a", "page").await;
+        assert!(cache
+            .get("http://libgen.invalid/a", CachePolicy::Page)
+            .await
+            .is_some());
+        cache.clear().await.unwrap();
+        assert!(cache
+            .get("http://libgen.invalid/a", CachePolicy::Page)
+            .await
+            .is_none());
+        assert!(pages.join("notes.json").exists());
+        assert!(pages.join("old.txt").exists());
+        assert!(directory.path().join("settings.json").exists());
+    }
+}
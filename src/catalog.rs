@@ -0,0 +1,927 @@
+use core::fmt;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    fs::OpenOptions,
+    io::{self, BufRead, BufReader, BufWriter, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+use tokio::{
+    fs,
+    io::{AsyncBufReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+use crate::{
+    api::BookMetadata,
+    book::{BookDetails, LibgenBook, Section},
+    fiction::{FictionColumn, FictionQuery},
+    identifiers::{Isbn, Md5},
+    parser::SearchColumn,
+};
+
+/// How many books a local search returns at most, like a full libgen results page
+pub const SEARCH_LIMIT: usize = 100;
+
+/// Column order of the `updated` table, for dumps whose INSERTs don't name their columns
+const UPDATED_COLUMNS: [&str; 47] = [
+    "ID",
+    "Title",
+    "VolumeInfo",
+    "Series",
+    "Periodical",
+    "Author",
+    "Year",
+    "Edition",
+    "Publisher",
+    "City",
+    "Pages",
+    "PagesInFile",
+    "Language",
+    "Topic",
+    "Library",
+    "Issue",
+    "Identifier",
+    "ISSN",
+    "ASIN",
+    "UDC",
+    "LBC",
+    "DDC",
+    "LCC",
+    "Doi",
+    "Googlebookid",
+    "OpenLibraryID",
+    "Commentary",
+    "DPI",
+    "Color",
+    "Cleaned",
+    "Orientation",
+    "Paginated",
+    "Scanned",
+    "Bookmarked",
+    "Searchable",
+    "Filesize",
+    "Extension",
+    "MD5",
+    "Generic",
+    "Visible",
+    "Locator",
+    "Local",
+    "TimeAdded",
+    "TimeLastModified",
+    "Coverurl",
+    "Tags",
+    "IdentifierWODash",
+];
+
+/// Column order of the `fiction` table
+const FICTION_COLUMNS: [&str; 24] = [
+    "ID",
+    "MD5",
+    "Title",
+    "Author",
+    "Series",
+    "Edition",
+    "Language",
+    "Year",
+    "Publisher",
+    "Pages",
+    "Identifier",
+    "GooglebookID",
+    "ASIN",
+    "Coverurl",
+    "Extension",
+    "Filesize",
+    "Library",
+    "Issue",
+    "Locator",
+    "Commentary",
+    "Generic",
+    "Visible",
+    "TimeAdded",
+    "TimeLastModified",
+];
+
+/// Errors from importing a dump or reading the local catalog
+#[derive(Debug)]
+pub enum CatalogError {
+    /// The dump or the catalog could not be read or written.
+    IOError(String),
+    /// The dump or the catalog is malformed.
+    ParsingError(String),
+    /// The catalog changed under its index, which has to be updated.
+    StaleIndex,
+}
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CatalogError::IOError(err) => write!(f, "IOError: {}", err),
+            CatalogError::ParsingError(err) => write!(f, "ParsingError: {}", err),
+            CatalogError::StaleIndex => write!(f, "StaleIndex"),
+        }
+    }
+}
+
+impl std::error::Error for CatalogError {}
+
+impl From<io::Error> for CatalogError {
+    fn from(error: io::Error) -> Self {
+        CatalogError::IOError(error.to_string())
+    }
+}
+
+impl From<serde_json::Error> for CatalogError {
+    fn from(error: serde_json::Error) -> Self {
+        CatalogError::ParsingError(error.to_string())
+    }
+}
+
+/// The tables of a libgen dump that hold books
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpTable {
+    /// `updated`, the main collection
+    Updated,
+    /// `fiction`
+    Fiction,
+}
+
+impl DumpTable {
+    fn from_name(name: &str) -> Option<DumpTable> {
+        match name {
+            "updated" => Some(DumpTable::Updated),
+            "fiction" => Some(DumpTable::Fiction),
+            _ => None,
+        }
+    }
+
+    fn section(self) -> Section {
+        match self {
+            DumpTable::Updated => Section::NonFiction,
+            DumpTable::Fiction => Section::Fiction,
+        }
+    }
+
+    fn default_columns(self) -> Vec<String> {
+        let columns: &[&str] = match self {
+            DumpTable::Updated => &UPDATED_COLUMNS,
+            DumpTable::Fiction => &FICTION_COLUMNS,
+        };
+        columns.iter().map(|column| column.to_string()).collect()
+    }
+}
+
+/// A book in the local catalog
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CatalogRecord {
+    /// The book, as a search would have found it
+    pub book: LibgenBook,
+    /// Language of the book
+    pub language: Option<String>,
+    /// Exact size and hashes, as far as the dump had them
+    #[serde(default)]
+    pub details: BookDetails,
+}
+
+/// What an import added
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    /// Books written to the catalog
+    pub books: usize,
+    /// Rows without an id or md5, and books libgen hides
+    pub skipped: usize,
+}
+
+/// Reads the `updated` and `fiction` INSERTs of a MySQL dump, writing one JSON record
+/// per line to `store`
+///
+/// Column names are taken from the INSERT or the tables CREATE statement, falling back
+/// to libgens usual column order. Other tables are passed over.
+pub fn import_dump(
+    mut dump: impl BufRead,
+    mut store: impl Write,
+) -> Result<ImportSummary, CatalogError> {
+    let mut summary = ImportSummary::default();
+    let mut created_columns: Vec<(DumpTable, Vec<String>)> = Vec::new();
+    let mut statement = Vec::new();
+
+    while read_statement(&mut dump, &mut statement)? {
+        let mut cursor = SqlCursor::new(&statement);
+        if cursor.keyword("CREATE") && cursor.keyword("TABLE") {
+            if let Some(table) = cursor
+                .identifier()
+                .as_deref()
+                .and_then(DumpTable::from_name)
+            {
+                created_columns.retain(|(known, _)| *known != table);
+                created_columns.push((table, column_definitions(&statement)));
+            }
+            continue;
+        }
+
+        let Some(insert) = parse_insert(&statement)? else {
+            continue;
+        };
+        let Some(table) = DumpTable::from_name(&insert.table) else {
+            continue;
+        };
+        let columns = insert
+            .columns
+            .or_else(|| {
+                created_columns
+                    .iter()
+                    .find(|(known, _)| *known == table)
+                    .map(|(_, columns)| columns.clone())
+            })
+            .unwrap_or_else(|| table.default_columns());
+
+        for row in insert.rows {
+            match record_from_row(table, &columns, row) {
+                Some(record) => {
+                    serde_json::to_writer(&mut store, &record)?;
+                    store.write_all(b"\n")?;
+                    summary.books += 1;
+                }
+                None => summary.skipped += 1,
+            }
+        }
+    }
+    Ok(summary)
+}
+
+fn record_from_row(
+    table: DumpTable,
+    columns: &[String],
+    row: Vec<Option<String>>,
+) -> Option<CatalogRecord> {
+    let fields: Map<String, Value> = columns
+        .iter()
+        .zip(row)
+        .filter_map(|(name, value)| Some((name.to_lowercase(), Value::String(value?))))
+        .collect();
+    // Libgen hides removed and copyrighted books by filling in `Visible`
+    if fields
+        .get("visible")
+        .and_then(Value::as_str)
+        .is_some_and(|visible| !visible.trim().is_empty())
+    {
+        return None;
+    }
+
+    let metadata = BookMetadata::from_json(&fields)?;
+    Some(CatalogRecord {
+        book: LibgenBook {
+            section: table.section(),
+            ..metadata.book
+        },
+        language: metadata.language,
+        details: metadata.details,
+    })
+}
+
+/// Reads up to the next `;` outside of quotes, skipping comment lines
+///
+/// Returns `false` once the dump is exhausted.
+fn read_statement(dump: &mut impl BufRead, statement: &mut Vec<u8>) -> io::Result<bool> {
+    statement.clear();
+    let mut quote: Option<u8> = None;
+    let mut escaped = false;
+    let mut line_start = true;
+    let mut comment = false;
+
+    loop {
+        let buffer = dump.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(!statement.trim_ascii().is_empty());
+        }
+
+        let mut used = 0;
+        let mut complete = false;
+        for &byte in buffer {
+            used += 1;
+            if comment {
+                if byte == b'\n' {
+                    comment = false;
+                    line_start = true;
+                }
+                continue;
+            }
+            if let Some(open) = quote {
+                statement.push(byte);
+                if escaped {
+                    escaped = false;
+                } else if byte == b'\\' && open != b'`' {
+                    escaped = true;
+                } else if byte == open {
+                    quote = None;
+                }
+                continue;
+            }
+            match byte {
+                b'-' | b'#' if line_start => comment = true,
+                b';' => {
+                    complete = true;
+                    break;
+                }
+                _ => {
+                    statement.push(byte);
+                    if matches!(byte, b'\'' | b'"' | b'`') {
+                        quote = Some(byte);
+                    }
+                    if byte == b'\n' {
+                        line_start = true;
+                    } else if !byte.is_ascii_whitespace() {
+                        line_start = false;
+                    }
+                }
+            }
+        }
+        dump.consume(used);
+        if complete {
+            return Ok(true);
+        }
+    }
+}
+
+/// The column names of a CREATE TABLE statement, one definition per line
+fn column_definitions(statement: &[u8]) -> Vec<String> {
+    statement
+        .split(|byte| *byte == b'\n')
+        .filter_map(|line| SqlCursor::new(line.trim_ascii()).quoted_identifier())
+        .collect()
+}
+
+struct Insert {
+    table: String,
+    columns: Option<Vec<String>>,
+    rows: Vec<Vec<Option<String>>>,
+}
+
+/// Parses an `INSERT INTO t [(columns)] VALUES (..),(..)` statement, `None` for
+/// other statements
+fn parse_insert(statement: &[u8]) -> Result<Option<Insert>, CatalogError> {
+    let mut cursor = SqlCursor::new(statement);
+    if !(cursor.keyword("INSERT") || cursor.keyword("REPLACE")) {
+        return Ok(None);
+    }
+    cursor.keyword("IGNORE");
+    cursor.keyword("INTO");
+    let Some(table) = cursor.identifier() else {
+        return Err(cursor.error("table name"));
+    };
+
+    let columns = if cursor.eat(b'(') {
+        let mut columns = Vec::new();
+        loop {
+            columns.push(cursor.identifier().ok_or_else(|| cursor.error("column"))?);
+            if cursor.eat(b')') {
+                break;
+            }
+            if !cursor.eat(b',') {
+                return Err(cursor.error("`,` or `)`"));
+            }
+        }
+        Some(columns)
+    } else {
+        None
+    };
+    if !cursor.keyword("VALUES") {
+        return Err(cursor.error("VALUES"));
+    }
+
+    let mut rows = Vec::new();
+    loop {
+        if !cursor.eat(b'(') {
+            return Err(cursor.error("`(`"));
+        }
+        let mut row = Vec::new();
+        loop {
+            row.push(cursor.value()?);
+            if cursor.eat(b')') {
+                break;
+            }
+            if !cursor.eat(b',') {
+                return Err(cursor.error("`,` or `)`"));
+            }
+        }
+        rows.push(row);
+        if !cursor.eat(b',') {
+            break;
+        }
+    }
+    Ok(Some(Insert {
+        table,
+        columns,
+        rows,
+    }))
+}
+
+/// Walks the bytes of one statement
+struct SqlCursor<'a> {
+    sql: &'a [u8],
+    at: usize,
+}
+
+impl<'a> SqlCursor<'a> {
+    fn new(sql: &'a [u8]) -> SqlCursor<'a> {
+        SqlCursor { sql, at: 0 }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.sql.get(self.at).is_some_and(u8::is_ascii_whitespace) {
+            self.at += 1;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.sql.get(self.at) == Some(&byte);
+        if found {
+            self.at += 1;
+        }
+        found
+    }
+
+    /// Consumes `word` if it comes next, ignoring case
+    fn keyword(&mut self, word: &str) -> bool {
+        self.skip_whitespace();
+        let end = self.at + word.len();
+        let found = self
+            .sql
+            .get(self.at..end)
+            .is_some_and(|next| next.eq_ignore_ascii_case(word.as_bytes()))
+            && !self
+                .sql
+                .get(end)
+                .is_some_and(|after| after.is_ascii_alphanumeric() || *after == b'_');
+        if found {
+            self.at = end;
+        }
+        found
+    }
+
+    fn quoted_identifier(&mut self) -> Option<String> {
+        if !self.eat(b'`') {
+            return None;
+        }
+        let length = self.sql[self.at..].iter().position(|byte| *byte == b'`')?;
+        let name = String::from_utf8_lossy(&self.sql[self.at..self.at + length]).into_owned();
+        self.at += length + 1;
+        Some(name)
+    }
+
+    fn identifier(&mut self) -> Option<String> {
+        self.skip_whitespace();
+        if self.sql.get(self.at) == Some(&b'`') {
+            return self.quoted_identifier();
+        }
+        let start = self.at;
+        while self
+            .sql
+            .get(self.at)
+            .is_some_and(|byte| byte.is_ascii_alphanumeric() || *byte == b'_')
+        {
+            self.at += 1;
+        }
+        (self.at > start).then(|| String::from_utf8_lossy(&self.sql[start..self.at]).into_owned())
+    }
+
+    /// A quoted string, a bare number or `NULL`
+    fn value(&mut self) -> Result<Option<String>, CatalogError> {
+        self.skip_whitespace();
+        if self.sql.get(self.at) != Some(&b'\'') {
+            let start = self.at;
+            while self
+                .sql
+                .get(self.at)
+                .is_some_and(|byte| !matches!(byte, b',' | b')') && !byte.is_ascii_whitespace())
+            {
+                self.at += 1;
+            }
+            let token = String::from_utf8_lossy(&self.sql[start..self.at]).into_owned();
+            return match token.as_str() {
+                "" => Err(self.error("value")),
+                null if null.eq_ignore_ascii_case("NULL") => Ok(None),
+                _ => Ok(Some(token)),
+            };
+        }
+
+        self.at += 1;
+        let mut value = Vec::new();
+        loop {
+            let Some(&byte) = self.sql.get(self.at) else {
+                return Err(self.error("closing quote"));
+            };
+            self.at += 1;
+            match byte {
+                b'\\' => {
+                    let Some(&escaped) = self.sql.get(self.at) else {
+                        return Err(self.error("escaped character"));
+                    };
+                    self.at += 1;
+                    match escaped {
+                        b'0' => value.push(0),
+                        b'b' => value.push(8),
+                        b'n' => value.push(b'\n'),
+                        b'r' => value.push(b'\r'),
+                        b't' => value.push(b'\t'),
+                        b'Z' => value.push(26),
+                        // Kept escaped by MySQL too, they only matter to LIKE
+                        b'%' | b'_' => value.extend([b'\\', escaped]),
+                        other => value.push(other),
+                    }
+                }
+                b'\'' if self.sql.get(self.at) == Some(&b'\'') => {
+                    self.at += 1;
+                    value.push(b'\'');
+                }
+                b'\'' => break,
+                other => value.push(other),
+            }
+        }
+        Ok(Some(String::from_utf8_lossy(&value).into_owned()))
+    }
+
+    fn error(&self, expected: &str) -> CatalogError {
+        CatalogError::ParsingError(format!("expected {} at byte {}", expected, self.at))
+    }
+}
+
+/// Whether every word of `text` appears in `field`, ignoring case
+fn contains_words(field: &str, text: &str) -> bool {
+    let field = field.to_lowercase();
+    text.to_lowercase()
+        .split_whitespace()
+        .all(|word| field.contains(word))
+}
+
+fn author_names(book: &LibgenBook) -> String {
+    book.authors
+        .iter()
+        .map(|author| author.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Whether a book matches `text` in `column`, the way a libgen search would
+pub fn matches_column(book: &LibgenBook, column: SearchColumn, text: &str) -> bool {
+    match column {
+        SearchColumn::Title => contains_words(&book.title, text),
+        SearchColumn::Author => contains_words(&author_names(book), text),
+        SearchColumn::Series => book
+            .series
+            .as_deref()
+            .is_some_and(|series| contains_words(series, text)),
+        SearchColumn::Publisher => contains_words(&book.publisher, text),
+        SearchColumn::Isbn => match text.parse::<Isbn>() {
+            Ok(isbn) => book
+                .isbns
+                .iter()
+                .any(|listed| listed.to_isbn13() == isbn.to_isbn13()),
+            Err(_) => false,
+        },
+    }
+}
+
+/// Searches books imported from libgens database dumps, without touching the network
+///
+/// Offers the searches of [`crate::scraper::LibgenClient`], books found can be
+/// downloaded with it as usual.
+///
+/// The catalog is a JSON line per record that is only ever appended to, so a book
+/// imported again gets a second record and the latest one counts. Every search
+/// reads the whole file, a [`crate::index::CatalogIndex`] answers without doing so.
+#[derive(Debug, Clone)]
+pub struct LocalCatalog {
+    path: PathBuf,
+}
+
+impl LocalCatalog {
+    /// Uses the catalog at `path`, which [`LocalCatalog::import`] creates
+    pub fn new(path: impl Into<PathBuf>) -> LocalCatalog {
+        LocalCatalog { path: path.into() }
+    }
+
+    /// The file holding the catalog
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Adds the books of a dump file to the catalog, see [`import_dump`]
+    ///
+    /// Books already in the catalog are replaced by their new record.
+    pub async fn import(&self, dump: impl AsRef<Path>) -> Result<ImportSummary, CatalogError> {
+        let dump = dump.as_ref().to_path_buf();
+        let store = self.path.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut writer =
+                BufWriter::new(OpenOptions::new().create(true).append(true).open(store)?);
+            let summary = import_dump(BufReader::new(std::fs::File::open(dump)?), &mut writer)?;
+            writer.flush()?;
+            Ok(summary)
+        })
+        .await
+        .map_err(|err| CatalogError::IOError(err.to_string()))?
+    }
+
+    /// The latest record of every book `keep` accepts, in catalog order
+    ///
+    /// Older records of a book are passed over, even when `keep` would accept them.
+    pub async fn scan(
+        &self,
+        mut keep: impl FnMut(&CatalogRecord) -> bool,
+    ) -> Result<Vec<CatalogRecord>, CatalogError> {
+        let mut found: Vec<Option<CatalogRecord>> = Vec::new();
+        // Where the records kept so far are in `found`
+        let mut positions: HashMap<Md5, usize> = HashMap::new();
+        self.scan_from(0, |_, record| {
+            if let Some(earlier) = positions.remove(&record.book.libgen_md5) {
+                found[earlier] = None;
+            }
+            if keep(&record) {
+                positions.insert(record.book.libgen_md5.clone(), found.len());
+                found.push(Some(record));
+            }
+        })
+        .await?;
+        Ok(found.into_iter().flatten().collect())
+    }
+
+    /// Hands every record from byte `offset` on to `each`, along with its offset
+    ///
+    /// Returns where the last complete record ends, a line still being written is
+    /// left for the next scan.
+    pub async fn scan_from(
+        &self,
+        offset: u64,
+        mut each: impl FnMut(u64, CatalogRecord),
+    ) -> Result<u64, CatalogError> {
+        let mut file = fs::File::open(&self.path).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        let mut reader = tokio::io::BufReader::new(file);
+        let mut line = Vec::new();
+        let mut position = offset;
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line).await?;
+            if read == 0 || line.last() != Some(&b'\n') {
+                return Ok(position);
+            }
+            if !line.trim_ascii().is_empty() {
+                each(position, serde_json::from_slice(&line)?);
+            }
+            position += read as u64;
+        }
+    }
+
+    /// The record starting at byte `offset`, as handed out by [`LocalCatalog::scan_from`]
+    pub async fn record_at(&self, offset: u64) -> Result<CatalogRecord, CatalogError> {
+        let mut file = fs::File::open(&self.path).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        let mut line = Vec::new();
+        tokio::io::BufReader::new(file)
+            .read_until(b'\n', &mut line)
+            .await?;
+        Ok(serde_json::from_slice(&line)?)
+    }
+
+    /// Adds books found online, e.g. by [`crate::feed::RecentFeed`], to the catalog
+    pub async fn add_books(&self, books: &[LibgenBook]) -> Result<(), CatalogError> {
+        let mut lines = Vec::new();
+        for book in books {
+            serde_json::to_writer(
+                &mut lines,
+                &CatalogRecord {
+                    book: book.clone(),
+                    language: None,
+                    details: BookDetails::default(),
+                },
+            )?;
+            lines.push(b'\n');
+        }
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(&lines).await?;
+        file.flush().await?;
+        Ok(())
+    }
+
+    /// Search for a book based on its title, see [`crate::scraper::LibgenClient::search_book_by_title`]
+    pub async fn search_book_by_title(
+        &self,
+        title: &str,
+    ) -> Result<Option<LibgenBook>, CatalogError> {
+        let title = title.trim().to_lowercase();
+        Ok(self
+            .search(SearchColumn::Title, &title)
+            .await?
+            .into_iter()
+            .find(|book| book.title.trim().to_lowercase().starts_with(&title)))
+    }
+
+    /// Searches one column of the main collection, newest first
+    pub async fn search(
+        &self,
+        column: SearchColumn,
+        text: &str,
+    ) -> Result<Vec<LibgenBook>, CatalogError> {
+        let mut books = self
+            .scan(|record| {
+                record.book.section == Section::NonFiction
+                    && matches_column(&record.book, column, text)
+            })
+            .await?;
+        books.sort_by_key(|record| std::cmp::Reverse(record.book.year));
+        Ok(books
+            .into_iter()
+            .take(SEARCH_LIMIT)
+            .map(|record| record.book)
+            .collect())
+    }
+
+    /// Looks a book up by its MD5, in any section
+    pub async fn get_by_md5(&self, md5: &Md5) -> Result<Option<LibgenBook>, CatalogError> {
+        Ok(self.record_by_md5(md5).await?.map(|record| record.book))
+    }
+
+    /// The latest record of a book, with its language and details
+    pub async fn record_by_md5(&self, md5: &Md5) -> Result<Option<CatalogRecord>, CatalogError> {
+        Ok(self
+            .scan(|record| &record.book.libgen_md5 == md5)
+            .await?
+            .pop())
+    }
+
+    /// Searches the fiction section, in catalog order
+    pub async fn search_fiction(
+        &self,
+        query: &FictionQuery,
+    ) -> Result<Vec<LibgenBook>, CatalogError> {
+        let books = self
+            .scan(|record| {
+                let book = &record.book;
+                let series = book.series.as_deref().unwrap_or_default();
+                let text_matches = match query.column {
+                    FictionColumn::All => contains_words(
+                        &format!("{} {} {}", book.title, author_names(book), series),
+                        &query.text,
+                    ),
+                    FictionColumn::Title => contains_words(&book.title, &query.text),
+                    FictionColumn::Author => contains_words(&author_names(book), &query.text),
+                    FictionColumn::Series => contains_words(series, &query.text),
+                };
+                book.section == Section::Fiction
+                    && text_matches
+                    && query.language.as_ref().is_none_or(|language| {
+                        record
+                            .language
+                            .as_ref()
+                            .is_some_and(|listed| listed.eq_ignore_ascii_case(language))
+                    })
+                    && query
+                        .format
+                        .as_ref()
+                        .is_none_or(|format| book.file_type.eq_ignore_ascii_case(format))
+            })
+            .await?;
+        Ok(books
+            .into_iter()
+            .take(SEARCH_LIMIT)
+            .map(|record| record.book)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DUMP: &str = "-- MySQL dump, libgen's `bookwarrior`\n\
+        /*!40101 SET NAMES utf8 */;\n\
+        CREATE TABLE `updated` (\n\
+          `ID` int(15) unsigned NOT NULL AUTO_INCREMENT,\n\
+          `Title` varchar(2000) DEFAULT '',\n\
+          `Author` varchar(1000) DEFAULT '',\n\
+          `Year` varchar(14) DEFAULT '',\n\
+          `Identifier` varchar(300) DEFAULT '',\n\
+          `Extension` varchar(50) DEFAULT '',\n\
+          `MD5` char(32) DEFAULT NULL,\n\
+          `Visible` char(3) DEFAULT '',\n\
+          PRIMARY KEY (`ID`)\n\
+        ) ENGINE=MyISAM;\n\
+        INSERT INTO `updated` VALUES (3750,'Abstract and concrete categories: the joy of cats',\
+        'Jiri Adamek; Horst Herrlich','1990','0471609226','pdf','5fa82be26689a4e6f4415ea068d35a9d',''),\
+        (3751,'Cats; a \\'short\\' history','Jane Doe','2004','','epub','5eb63bbbe01eeed093cb22bb8f5acdc3',''),\
+        (3752,'Hidden cats','Jane Doe','2010','','pdf','098f6bcd4621d373cade4e832627b4f6','cpr');\n\
+        INSERT INTO `fiction` (`ID`,`MD5`,`Title`,`Author`,`Series`,`Language`,`Extension`) VALUES \
+        (12,'ad0234829205b9033196ba818f7a872b','The Cat Who Came','Lilian Braun','Cat Who','English','epub');\n";
+
+    #[test]
+    fn imports_dump_rows() {
+        let mut store = Vec::new();
+        let summary = import_dump(DUMP.as_bytes(), &mut store).unwrap();
+        assert_eq!(
+            summary,
+            ImportSummary {
+                books: 3,
+                skipped: 1
+            }
+        );
+
+        let records: Vec<CatalogRecord> = store
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(records[1].book.title, "Cats; a 'short' history");
+        assert_eq!(records[0].book.authors.len(), 2);
+        assert_eq!(records[0].book.isbns.len(), 1);
+        assert_eq!(records[2].book.section, Section::Fiction);
+        assert_eq!(records[2].language.as_deref(), Some("English"));
+    }
+
+    #[test]
+    fn rejects_broken_inserts() {
+        assert!(import_dump(
+            "INSERT INTO `updated` VALUES (1,'open".as_bytes(),
+            Vec::new()
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn searches_offline() {
+        let directory = tempfile::tempdir().unwrap();
+        let dump = directory.path().join("dump.sql");
+        std::fs::write(&dump, DUMP).unwrap();
+        let catalog = LocalCatalog::new(directory.path().join("catalog.jsonl"));
+        catalog.import(&dump).await.unwrap();
+
+        let found = catalog.search(SearchColumn::Title, "CATS").await.unwrap();
+        assert_eq!(
+            found
+                .iter()
+                .map(|book| book.libgen_id.unwrap().get())
+                .collect::<Vec<_>>(),
+            vec![3751, 3750]
+        );
+        let book = catalog
+            .search_book_by_title("abstract and concrete")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(book.year, Some(1990));
+        assert_eq!(
+            catalog
+                .search(SearchColumn::Isbn, "9780471609223")
+                .await
+                .unwrap(),
+            vec![book.clone()]
+        );
+        assert_eq!(
+            catalog.get_by_md5(&book.libgen_md5).await.unwrap(),
+            Some(book)
+        );
+
+        let fiction = catalog
+            .search_fiction(&FictionQuery::author("braun").language("english"))
+            .await
+            .unwrap();
+        assert_eq!(fiction.len(), 1);
+        assert_eq!(fiction[0].series.as_deref(), Some("Cat Who"));
+        assert!(catalog
+            .search_fiction(&FictionQuery::new("cat").format("pdf"))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn reimports_replace_earlier_records() {
+        let directory = tempfile::tempdir().unwrap();
+        let dump = directory.path().join("dump.sql");
+        std::fs::write(&dump, DUMP).unwrap();
+        let catalog = LocalCatalog::new(directory.path().join("catalog.jsonl"));
+        catalog.import(&dump).await.unwrap();
+        catalog.import(&dump).await.unwrap();
+
+        let found = catalog.search(SearchColumn::Title, "cats").await.unwrap();
+        assert_eq!(found.len(), 2);
+
+        // A record that no longer matches hides the earlier one that did
+        let mut renamed = found[0].clone();
+        renamed.title = "Dogs".to_owned();
+        catalog.add_books(&[renamed.clone()]).await.unwrap();
+        assert_eq!(
+            catalog.search(SearchColumn::Title, "cats").await.unwrap(),
+            vec![found[1].clone()]
+        );
+        assert_eq!(
+            catalog.get_by_md5(&renamed.libgen_md5).await.unwrap(),
+            Some(renamed)
+        );
+    }
+}
@@ -1,15 +1,43 @@
-use crate::book::LibgenBook;
+use crate::{
+    bandwidth::BandwidthLimiter,
+    book::{LibgenBook, Section},
+    filename::FilenameTemplate,
+    identifiers::Md5,
+    ipfs::{Cid, DEFAULT_IPFS_GATEWAYS},
+    parser::Mirror,
+    processor::Processor,
+    scimag::LibgenArticle,
+};
+use bytes::Bytes;
 use core::fmt;
-use lazy_static::lazy_static;
-use regex::Regex;
+use md5::{Digest, Md5 as Md5Hasher};
+use reqwest::{Client, StatusCode};
+use scraper::Html;
+use serde::{Deserialize, Serialize};
 use std::{
-    error::Error,
-    f64::consts::E,
-    fs::File,
-    io::{self, Read, Write},
-    net::TcpStream,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    fs,
+    io::{AsyncWrite, AsyncWriteExt},
 };
-// Define your custom error enum
+
+const DOWNLOAD_TIMEOUT: u64 = 300;
+/// Lists the direct link of books whose link can't be built
+pub(crate) const DOWNLOAD_PAGE_URL: &str = "http://library.lol";
+
+/// Numbers the part files of this process
+static PART_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Errors that can occur while downloading a book
 #[derive(Debug)]
 pub enum DownloadError {
     /// Connection error while collecting data.
@@ -20,32 +48,32 @@ pub enum DownloadError {
     DirectoryError,
     /// Other IO error occurred.
     IOError(String),
+    /// The downloaded file does not match the books md5.
+    ChecksumMismatch {
+        /// The md5 libgen lists for the book
+        expected: Md5,
+        /// The md5 of the downloaded bytes
+        actual: Md5,
+    },
 }
 
 // Implement Display for DownloadError
 impl fmt::Display for DownloadError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let error_str = match self {
-            DownloadError::ConnectionError(ref err) => err.as_str(),
-            DownloadError::DownloadError(ref err) => err.as_str(),
-            DownloadError::DirectoryError => "DirectoryError",
-            DownloadError::IOError(ref err) => err.as_str(),
-        };
-        write!(f, "{}", error_str)
+        match self {
+            DownloadError::ConnectionError(ref err) => write!(f, "{}", err),
+            DownloadError::DownloadError(ref err) => write!(f, "{}", err),
+            DownloadError::DirectoryError => write!(f, "DirectoryError"),
+            DownloadError::IOError(ref err) => write!(f, "{}", err),
+            DownloadError::ChecksumMismatch { expected, actual } => {
+                write!(f, "ChecksumMismatch: expected {} got {}", expected, actual)
+            }
+        }
     }
 }
-lazy_static! {
-    static ref RE: Regex = Regex::new(r#"[\/:*?"<>|]"#).unwrap();
-}
 
-#[derive(Debug, PartialEq)]
-#[doc = r" The data collected from a search result."]
-pub struct Downloader {
-    /// Request headers
-    request_ops: String,
-    download_path: Option<String>,
-    hosts: Vec<String>,
-}
+impl std::error::Error for DownloadError {}
+
 impl From<io::Error> for DownloadError {
     fn from(error: io::Error) -> Self {
         match error.kind() {
@@ -58,21 +86,94 @@ impl From<io::Error> for DownloadError {
         }
     }
 }
+
+impl From<reqwest::Error> for DownloadError {
+    fn from(error: reqwest::Error) -> Self {
+        DownloadError::ConnectionError(error.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[doc = r" A book that was saved to disk."]
+pub struct DownloadedFile {
+    /// Where the book was written
+    pub path: PathBuf,
+    /// Size of the file in bytes
+    pub size: u64,
+    /// The md5 of the downloaded bytes, matches the books md5
+    pub md5: Md5,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[doc = r" A book that was streamed into a caller provided sink."]
+pub struct StreamedDownload {
+    /// Bytes written to the sink
+    pub size: u64,
+    /// The md5 of the written bytes, matches the books md5
+    pub md5: Md5,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[doc = r" How far along a download is, passed to the progress callback."]
+pub struct DownloadProgress<'a> {
+    /// The md5 of the book being downloaded
+    pub md5: &'a Md5,
+    /// Bytes received so far
+    pub downloaded: u64,
+    /// The size the mirror reported, if any
+    pub total: Option<u64>,
+}
+
+/// Called after every chunk received by any download
+pub type ProgressCallback = Arc<dyn Fn(DownloadProgress<'_>) + Send + Sync>;
+
+#[derive(Clone)]
+struct ProgressHook(ProgressCallback);
+
+impl fmt::Debug for ProgressHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressHook")
+    }
+}
+
+#[derive(Debug, Clone)]
+#[doc = r" Downloads books from the libgen file mirrors."]
+pub struct Downloader {
+    /// The request client
+    client: Client,
+    download_path: Option<String>,
+    /// Base urls of the file mirrors, tried in order
+    hosts: Vec<String>,
+    /// IPFS gateways, tried in order
+    ipfs_gateways: Vec<String>,
+    filename_template: FilenameTemplate,
+    /// Read for the link of books without a direct one
+    download_page: Mirror,
+    /// Shared by every download
+    global_limit: BandwidthLimiter,
+    /// Each download gets its own bucket at this rate
+    per_download_limit: BandwidthLimiter,
+    progress: Option<ProgressHook>,
+}
+
 impl Downloader {
     /// Downloader object
     pub fn new(download_path: Option<String>) -> Downloader {
+        Downloader::with_client(Client::new(), download_path)
+    }
+
+    /// Downloader using an existing client, e.g. one configured with a proxy
+    pub fn with_client(client: Client, download_path: Option<String>) -> Downloader {
         Downloader {
-            request_ops: "\r\n\
-			Host: download.library.lol\r\n\
-			Accept: text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8,application/signed-exchange;v=b3;q=0.7\r\n\
-			Accept-Encoding: gzip, deflate, br\r\n\
-			Accept-Language: en-CA,en;q=0.9\r\n\
-			Cache-Control: max-age=0\r\n\
-			Connection: keep-alive\r\n\
-			\r\n".to_owned() ,
+            client,
             download_path: download_path.or_else(|| Some(String::from("."))),
-            hosts:  vec!["download.library.lol:80".to_string()]
-
+            hosts: vec!["https://download.library.lol".to_string()],
+            ipfs_gateways: DEFAULT_IPFS_GATEWAYS.map(String::from).to_vec(),
+            filename_template: FilenameTemplate::default(),
+            download_page: Mirror::new(DOWNLOAD_PAGE_URL, Processor::new()),
+            global_limit: BandwidthLimiter::default(),
+            per_download_limit: BandwidthLimiter::default(),
+            progress: None,
         }
     }
 
@@ -81,62 +182,683 @@ impl Downloader {
         self.download_path = Some(new_path)
     }
 
+    /// Changes how downloaded books are named
+    pub fn set_filename_template(&mut self, template: FilenameTemplate) {
+        self.filename_template = template;
+    }
+
+    /// Replaces the file mirrors, e.g. `https://download.library.lol`
+    pub fn set_hosts(&mut self, hosts: Vec<String>) {
+        self.hosts = hosts;
+    }
+
+    /// Reads the links of books without a direct one from this site instead of library.lol
+    pub fn set_download_page(&mut self, mirror: Mirror) {
+        self.download_page = mirror;
+    }
+
+    /// Caps the combined speed of all downloads in bytes per second, `None` removes the cap
+    ///
+    /// Clones of this downloader share the cap, so this also applies to downloads
+    /// that are already running.
+    pub fn set_bandwidth_limit(&self, bytes_per_second: Option<u64>) {
+        self.global_limit.set_rate(bytes_per_second);
+    }
+
+    /// Caps the speed of each individual download in bytes per second
+    pub fn set_per_download_limit(&self, bytes_per_second: Option<u64>) {
+        self.per_download_limit.set_rate(bytes_per_second);
+    }
+
+    /// Sets a callback that is told about every chunk downloaded
+    pub fn set_progress_callback(&mut self, callback: Option<ProgressCallback>) {
+        self.progress = callback.map(ProgressHook);
+    }
+
+    /// Replaces the IPFS gateways, e.g. `http://127.0.0.1:8080` for a local node
+    pub fn set_ipfs_gateways(&mut self, gateways: Vec<String>) {
+        self.ipfs_gateways = gateways;
+    }
+
+    /// The file mirrors, in the order they are tried
+    pub fn hosts(&self) -> &[String] {
+        &self.hosts
+    }
+
     /// Gets the current download directory
     pub fn get_download_path(self) -> Option<String> {
         self.download_path
     }
 
-    fn get_book_download_headers(book: &LibgenBook) -> Option<String> {
-        let binding = book.build_direct_download_url();
-        let download_path = binding.as_ref().unwrap();
-        if let Some(index) = download_path.find("/main/") {
-            return Some(download_path[index..].to_string());
+    /// The path and query of the books direct download link
+    fn get_book_download_path(book: &LibgenBook) -> Option<String> {
+        let binding = book.build_direct_download_url().ok()?;
+        ["/main/", "/fiction/"]
+            .iter()
+            .find_map(|section| binding.find(section))
+            .map(|index| binding[index..].to_string())
+    }
+
+    fn create_book_download_name(&self, book: &LibgenBook) -> PathBuf {
+        self.filename_template.render(book)
+    }
+
+    /// The books download url on a mirror
+    fn book_url(book: &LibgenBook, host: &str) -> Result<String, DownloadError> {
+        let book_path = Self::get_book_download_path(book).ok_or_else(|| {
+            DownloadError::ConnectionError("Failed to create download url".to_string())
+        })?;
+        Ok(format!("{}{}", host.trim_end_matches('/'), book_path))
+    }
+
+    /// Whether the books link can be built for every mirror
+    ///
+    /// Fiction search results lack the id the link needs, their link is read from
+    /// the download page instead.
+    pub fn has_direct_link(book: &LibgenBook) -> bool {
+        Self::get_book_download_path(book).is_some()
+    }
+
+    /// The books download urls, one per mirror or the one on its download page
+    async fn book_urls(&self, book: &LibgenBook) -> Result<Vec<String>, DownloadError> {
+        if Self::has_direct_link(book) {
+            return self
+                .hosts
+                .iter()
+                .map(|host| Self::book_url(book, host))
+                .collect();
         }
-        None
+        Ok(vec![self.download_page_url(book).await?])
     }
 
-    fn create_book_download_name(book: &LibgenBook) -> String {
-        let temp = [book.title.clone(), book.file_type.clone()].join(".");
-        RE.replace_all(&temp, "_").to_string()
+    /// Reads the books direct link from its download page
+    async fn download_page_url(&self, book: &LibgenBook) -> Result<String, DownloadError> {
+        if book.section == Section::Scimag {
+            return Err(DownloadError::DownloadError(format!(
+                "Article {} is downloaded by its DOI",
+                book.libgen_md5
+            )));
+        }
+        let parser = self.download_page.parser();
+        let url = self.download_page.url(&parser.download_page_path(book));
+        let response = self
+            .client
+            .get(&url)
+            .timeout(Duration::from_secs(DOWNLOAD_TIMEOUT))
+            .send()
+            .await?;
+        if response.status() != StatusCode::OK {
+            return Err(DownloadError::DownloadError(format!(
+                "{} returned {}",
+                url,
+                response.status()
+            )));
+        }
+        let body = response.text().await?;
+        parser
+            .parse_download_url(&Html::parse_document(&body))
+            .ok_or_else(|| DownloadError::DownloadError("No download link listed".to_string()))
     }
 
-    #[doc = r"Downloads the book based on its direct link."]
-    pub fn download(&self, book: &LibgenBook) -> Result<(), DownloadError> {
-        let book_headers = Self::get_book_download_headers(&book).ok_or_else(|| {
-            DownloadError::ConnectionError("Failed to create download headers".to_string())
+    #[doc = r"Downloads the book, trying each mirror until one succeeds."]
+    pub async fn download(&self, book: &LibgenBook) -> Result<DownloadedFile, DownloadError> {
+        let mut last_error = DownloadError::ConnectionError("No download hosts".to_string());
+        for url in self.book_urls(book).await? {
+            match self.download_url_to_file(&url, book).await {
+                Ok(file) => return Ok(file),
+                // A bad file from one mirror doesn't mean the next is bad too
+                Err(err) => last_error = err,
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Downloads the book from a single mirror, without failing over
+    ///
+    /// Books without a direct link are downloaded from their download page instead.
+    pub async fn download_from_host(
+        &self,
+        book: &LibgenBook,
+        host: &str,
+    ) -> Result<DownloadedFile, DownloadError> {
+        let url = if Self::has_direct_link(book) {
+            Self::book_url(book, host)?
+        } else {
+            self.download_page_url(book).await?
+        };
+        self.download_url_to_file(&url, book).await
+    }
+
+    /// Downloads a scientific article by its DOI, trying each mirror until one succeeds
+    ///
+    /// The articles MD5 has to be known, the file is checked against it.
+    pub async fn download_article(
+        &self,
+        article: &LibgenArticle,
+    ) -> Result<DownloadedFile, DownloadError> {
+        let book = article.to_book().ok_or_else(|| {
+            DownloadError::DownloadError(format!("MD5 of {} is unknown", article.doi))
         })?;
+        let mut last_error = DownloadError::ConnectionError("No download hosts".to_string());
+        for host in &self.hosts {
+            let url = format!("{}{}", host.trim_end_matches('/'), article.download_path());
+            match self.download_url_to_file(&url, &book).await {
+                Ok(file) => return Ok(file),
+                Err(err) => last_error = err,
+            }
+        }
+        Err(last_error)
+    }
 
-        let built_request = format!("GET {} HTTP/1.1 {}", book_headers, self.request_ops);
-        println!("request preview: \n {:?}", built_request);
-
-        // Connect to the server
-        // TODO: use the hosts vec array
-        let mut stream = TcpStream::connect("download.library.lol:80")
-            .ok()
-            .expect("issue");
-
-        // Send the request
-        match stream.write_all(built_request.as_bytes()) {
-            Ok(()) => {
-                // Read the response body into a buffer
-                println!("request preview: \n {:?}", "yo");
-                // Write the buffer to a file
-                // TODO: Make filenames OS Friendly
-                let download_filename = match &self.download_path {
-                    Some(path) => format!("{}/{}", path, Self::create_book_download_name(book)),
-                    None => String::from("downloaded_book.pdf"),
+    /// Downloads the book by its IPFS CID, trying each gateway until one succeeds
+    ///
+    /// The file is checked against the books md5 just like a mirror download.
+    pub async fn download_from_ipfs(
+        &self,
+        book: &LibgenBook,
+        cid: &Cid,
+    ) -> Result<DownloadedFile, DownloadError> {
+        let mut last_error = DownloadError::ConnectionError("No IPFS gateways".to_string());
+        for gateway in &self.ipfs_gateways {
+            match self
+                .download_url_to_file(&cid.gateway_url(gateway), book)
+                .await
+            {
+                Ok(file) => return Ok(file),
+                Err(err) => last_error = err,
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Downloads the book into memory, trying each mirror until one succeeds
+    pub async fn download_to_bytes(&self, book: &LibgenBook) -> Result<Bytes, DownloadError> {
+        let mut last_error = DownloadError::ConnectionError("No download hosts".to_string());
+        for url in self.book_urls(book).await? {
+            // Every mirror gets a fresh buffer, so failing over is always safe
+            let mut buffer = Vec::new();
+            match self.stream_url(&url, &book.libgen_md5, &mut buffer).await {
+                Ok(_) => return Ok(Bytes::from(buffer)),
+                Err(err) => last_error = err,
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Streams the book into an async sink such as an upload or a socket
+    ///
+    /// Mirrors are tried in order until one succeeds, but only as long as nothing
+    /// was written to the sink. The checksum can only be checked once every byte
+    /// was written, so a [`DownloadError::ChecksumMismatch`] means the sink holds
+    /// bad data and should be discarded.
+    pub async fn download_to_writer<W>(
+        &self,
+        book: &LibgenBook,
+        sink: &mut W,
+    ) -> Result<StreamedDownload, DownloadError>
+    where
+        W: AsyncWrite + Unpin + ?Sized,
+    {
+        let mut last_error = DownloadError::ConnectionError("No download hosts".to_string());
+        for url in self.book_urls(book).await? {
+            let mut counted = CountingWriter {
+                inner: &mut *sink,
+                written: 0,
+            };
+            match self.stream_url(&url, &book.libgen_md5, &mut counted).await {
+                Ok(download) => return Ok(download),
+                Err(err) if counted.written == 0 => last_error = err,
+                Err(err) => return Err(err),
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Like [`Downloader::download_to_writer`], for blocking [`Write`] sinks
+    ///
+    /// Writes happen on the async task, so the sink should be fast, e.g. an
+    /// in-memory buffer or a pipe.
+    pub async fn download_to_sync_writer<W>(
+        &self,
+        book: &LibgenBook,
+        sink: &mut W,
+    ) -> Result<StreamedDownload, DownloadError>
+    where
+        W: Write + Unpin,
+    {
+        self.download_to_writer(book, &mut SyncWriter(sink)).await
+    }
+
+    /// Streams a url into a `.part` file next to the books final path, renaming it once verified
+    ///
+    /// An existing file is never replaced, the book is saved as `name (1).ext` instead.
+    pub(crate) async fn download_url_to_file(
+        &self,
+        url: &str,
+        book: &LibgenBook,
+    ) -> Result<DownloadedFile, DownloadError> {
+        let destination = match &self.download_path {
+            Some(path) => Path::new(path).join(self.create_book_download_name(book)),
+            None => return Err(DownloadError::DirectoryError),
+        };
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        // Every download gets its own part file, so two downloads of the same
+        // book or of books sharing a name never write into each other
+        let mut part_name = destination.as_os_str().to_owned();
+        part_name.push(format!(
+            ".{}.{}-{}.part",
+            book.libgen_md5,
+            std::process::id(),
+            PART_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let part_path = PathBuf::from(part_name);
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&part_path)
+            .await?;
+        match self.stream_url(url, &book.libgen_md5, &mut file).await {
+            Ok(download) => {
+                drop(file);
+                let destination = match reserve_destination(&destination).await {
+                    Ok(destination) => destination,
+                    Err(err) => {
+                        let _ = fs::remove_file(&part_path).await;
+                        return Err(err.into());
+                    }
                 };
-                let mut buffer = Vec::new();
-                stream.read_to_end(&mut buffer)?;
-                println!("read");
+                fs::rename(&part_path, &destination).await?;
+                Ok(DownloadedFile {
+                    path: destination,
+                    size: download.size,
+                    md5: download.md5,
+                })
+            }
+            Err(err) => {
+                drop(file);
+                let _ = fs::remove_file(&part_path).await;
+                Err(err)
+            }
+        }
+    }
 
-                println!("request : \n {:?}", download_filename);
-                let mut file = File::create(download_filename)?;
-                file.write_all(&buffer)?;
+    /// Streams a url into a sink, applying the bandwidth limits, reporting progress
+    /// and checking the bytes against `expected`
+    pub(crate) async fn stream_url<W>(
+        &self,
+        url: &str,
+        expected: &Md5,
+        sink: &mut W,
+    ) -> Result<StreamedDownload, DownloadError>
+    where
+        W: AsyncWrite + Unpin + ?Sized,
+    {
+        let mut response = self
+            .client
+            .get(url)
+            .timeout(Duration::from_secs(DOWNLOAD_TIMEOUT))
+            .send()
+            .await?;
 
-                return Ok(());
+        if response.status() != StatusCode::OK {
+            return Err(DownloadError::DownloadError(format!(
+                "{} returned {}",
+                url,
+                response.status()
+            )));
+        }
+
+        let total = response.content_length();
+        let mut hasher = Md5Hasher::new();
+        let mut size = 0;
+        let download_limit = self.per_download_limit.independent_bucket();
+        while let Some(chunk) = response.chunk().await? {
+            download_limit.acquire(chunk.len() as u64).await;
+            self.global_limit.acquire(chunk.len() as u64).await;
+            hasher.update(&chunk);
+            size += chunk.len() as u64;
+            sink.write_all(&chunk).await?;
+            if let Some(progress) = &self.progress {
+                (progress.0)(DownloadProgress {
+                    md5: expected,
+                    downloaded: size,
+                    total,
+                });
             }
-            Err(_) => panic!("Fuck"),
         }
+        sink.flush().await?;
+
+        let actual: Md5 = format!("{:x}", hasher.finalize()).parse().unwrap();
+        if &actual == expected {
+            Ok(StreamedDownload { size, md5: actual })
+        } else {
+            Err(DownloadError::ChecksumMismatch {
+                expected: expected.clone(),
+                actual,
+            })
+        }
+    }
+}
+
+/// Counts what was written so failover knows whether the sink was touched
+struct CountingWriter<'a, W: ?Sized> {
+    inner: &'a mut W,
+    written: u64,
+}
+
+impl<W: AsyncWrite + Unpin + ?Sized> AsyncWrite for CountingWriter<'_, W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut *self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.written += written as u64;
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Lets a blocking [`Write`] be used where an [`AsyncWrite`] is expected
+struct SyncWriter<'a, W>(&'a mut W);
+
+impl<W: Write + Unpin> AsyncWrite for SyncWriter<'_, W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(self.0.write(buf))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(self.0.flush())
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+/// Claims `destination`, or `name (n).ext` with the first free `n` if it is taken
+///
+/// The name is created empty so a concurrent download can't claim it too.
+async fn reserve_destination(destination: &Path) -> io::Result<PathBuf> {
+    let stem = destination
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = destination
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    let mut candidate = destination.to_path_buf();
+    for attempt in 1.. {
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+            .await
+        {
+            Ok(_) => return Ok(candidate),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                candidate =
+                    destination.with_file_name(format!("{} ({}){}", stem, attempt, extension));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{book::Section, identifiers::LibgenId};
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    /// Serves `body` to every request on a local port, returns the base url
+    pub(crate) async fn serve_bytes(body: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut request = [0u8; 4096];
+                    let _ = stream.read(&mut request).await;
+                    let header = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    );
+                    let _ = stream.write_all(header.as_bytes()).await;
+                    let _ = stream.write_all(body).await;
+                });
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    /// A client builder with a single libgen.rs style mirror at `host`
+    pub(crate) fn test_client(host: &str) -> crate::scraper::LibgenClientBuilder {
+        crate::scraper::LibgenClient::builder().mirror(Mirror::new(host, Processor::new()))
+    }
+
+    pub(crate) fn book_with_md5(md5: &str) -> LibgenBook {
+        LibgenBook {
+            libgen_id: Some(LibgenId(3750)),
+            libgen_md5: md5.parse().unwrap(),
+            file_type: "pdf".to_owned(),
+            title: "Abstract and concrete categories: the joy of cats".to_owned(),
+            authors: vec![],
+            publisher: "Wiley-Interscience".to_owned(),
+            isbns: vec![],
+            year: None,
+            series: None,
+            section: Section::NonFiction,
+        }
+    }
+
+    #[tokio::test]
+    async fn download_article_by_doi() {
+        let host = serve_bytes(b"hello world").await;
+        let directory = tempfile::tempdir().unwrap();
+        let mut downloader = Downloader::new(Some(directory.path().display().to_string()));
+        downloader.set_hosts(vec![host]);
+
+        let mut article = LibgenArticle {
+            doi: "10.1038/nature12373".parse().unwrap(),
+            title: "Nanometre-scale thermometry in a living cell".to_owned(),
+            authors: vec![],
+            journal: Some("Nature".to_owned()),
+            volume: None,
+            issue: None,
+            year: Some(2013),
+            pages: None,
+            md5: None,
+        };
+        assert!(downloader.download_article(&article).await.is_err());
+
+        article.md5 = Some("5eb63bbbe01eeed093cb22bb8f5acdc3".parse().unwrap());
+        let file = downloader.download_article(&article).await.unwrap();
+        assert!(file
+            .path
+            .ends_with("Nanometre-scale thermometry in a living cell.pdf"));
+    }
+
+    #[tokio::test]
+    async fn download_verifies_checksum() {
+        let host = serve_bytes(b"hello world").await;
+        let directory = tempfile::tempdir().unwrap();
+        let mut downloader = Downloader::new(Some(directory.path().display().to_string()));
+        downloader.set_hosts(vec![host]);
+
+        // md5 of "hello world"
+        let book = book_with_md5("5eb63bbbe01eeed093cb22bb8f5acdc3");
+        let file = downloader.download(&book).await.unwrap();
+
+        assert_eq!(file.size, 11);
+        assert_eq!(file.md5, book.libgen_md5);
+        assert_eq!(
+            file.path,
+            directory
+                .path()
+                .join("Abstract and concrete categories_ the joy of cats.pdf")
+        );
+        assert_eq!(std::fs::read(&file.path).unwrap(), b"hello world");
+    }
+
+    #[tokio::test]
+    async fn concurrent_downloads_never_overwrite() {
+        let host = serve_bytes(b"hello world").await;
+        let directory = tempfile::tempdir().unwrap();
+        let mut downloader = Downloader::new(Some(directory.path().display().to_string()));
+        downloader.set_hosts(vec![host]);
+        let existing = directory
+            .path()
+            .join("Abstract and concrete categories_ the joy of cats.pdf");
+        std::fs::write(&existing, b"an older book").unwrap();
+
+        let book = book_with_md5("5eb63bbbe01eeed093cb22bb8f5acdc3");
+        let (first, second) = tokio::join!(downloader.download(&book), downloader.download(&book));
+        let (first, second) = (first.unwrap(), second.unwrap());
+
+        assert_ne!(first.path, second.path);
+        assert_ne!(first.path, existing);
+        assert_ne!(second.path, existing);
+        assert_eq!(std::fs::read(&existing).unwrap(), b"an older book");
+        assert_eq!(std::fs::read(&first.path).unwrap(), b"hello world");
+        assert_eq!(std::fs::read(&second.path).unwrap(), b"hello world");
+        // No part files are left behind
+        assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 3);
+    }
+
+    #[tokio::test]
+    async fn download_rejects_wrong_checksum() {
+        let host = serve_bytes(b"not the book").await;
+        let directory = tempfile::tempdir().unwrap();
+        let mut downloader = Downloader::new(Some(directory.path().display().to_string()));
+        downloader.set_hosts(vec![host]);
+
+        let book = book_with_md5("5eb63bbbe01eeed093cb22bb8f5acdc3");
+        let result = downloader.download(&book).await;
+
+        assert!(matches!(
+            result,
+            Err(DownloadError::ChecksumMismatch { .. })
+        ));
+        assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn download_fails_over_to_next_host() {
+        let bad_host = serve_bytes(b"not the book").await;
+        let good_host = serve_bytes(b"hello world").await;
+        let directory = tempfile::tempdir().unwrap();
+        let mut downloader = Downloader::new(Some(directory.path().display().to_string()));
+        downloader.set_hosts(vec![bad_host, good_host]);
+
+        let book = book_with_md5("5eb63bbbe01eeed093cb22bb8f5acdc3");
+        assert!(downloader.download(&book).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn download_respects_per_download_limit() {
+        static BODY: [u8; 1500] = [b'a'; 1500];
+        let host = serve_bytes(&BODY).await;
+        let directory = tempfile::tempdir().unwrap();
+        let mut downloader = Downloader::new(Some(directory.path().display().to_string()));
+        downloader.set_hosts(vec![host]);
+        downloader.set_per_download_limit(Some(1000));
+
+        let book = book_with_md5("1f48b79d54a4df476c771e928bb5e0c7");
+        let start = std::time::Instant::now();
+        downloader.download(&book).await.unwrap();
+
+        // One second of burst, the remaining 500 bytes take half a second
+        assert!(start.elapsed() >= Duration::from_millis(450));
+    }
+
+    #[tokio::test]
+    async fn download_to_bytes_fails_over() {
+        let bad_host = serve_bytes(b"not the book").await;
+        let good_host = serve_bytes(b"hello world").await;
+        let mut downloader = Downloader::new(None);
+        downloader.set_hosts(vec![bad_host, good_host]);
+
+        let book = book_with_md5("5eb63bbbe01eeed093cb22bb8f5acdc3");
+        let bytes = downloader.download_to_bytes(&book).await.unwrap();
+        assert_eq!(&bytes[..], b"hello world");
+    }
+
+    #[tokio::test]
+    async fn download_to_writer_reports_progress() {
+        let host = serve_bytes(b"hello world").await;
+        let mut downloader = Downloader::new(None);
+        downloader.set_hosts(vec![host]);
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorder = seen.clone();
+        downloader.set_progress_callback(Some(Arc::new(move |progress| {
+            recorder
+                .lock()
+                .unwrap()
+                .push((progress.downloaded, progress.total))
+        })));
+
+        let book = book_with_md5("5eb63bbbe01eeed093cb22bb8f5acdc3");
+        let mut sink = Vec::new();
+        let download = downloader
+            .download_to_sync_writer(&book, &mut sink)
+            .await
+            .unwrap();
+
+        assert_eq!(sink, b"hello world");
+        assert_eq!(download.size, 11);
+        assert_eq!(seen.lock().unwrap().last(), Some(&(11, Some(11))));
+    }
+
+    #[tokio::test]
+    async fn download_to_writer_stops_after_writing() {
+        let bad_host = serve_bytes(b"not the book").await;
+        let good_host = serve_bytes(b"hello world").await;
+        let mut downloader = Downloader::new(None);
+        downloader.set_hosts(vec![bad_host, good_host]);
+
+        // The bad mirror already wrote into the sink, so it must not be reused
+        let book = book_with_md5("5eb63bbbe01eeed093cb22bb8f5acdc3");
+        let mut sink = Vec::new();
+        let result = downloader.download_to_writer(&book, &mut sink).await;
+        assert!(matches!(
+            result,
+            Err(DownloadError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn download_from_ipfs_gateways() {
+        let bad_gateway = serve_bytes(b"not the book").await;
+        let good_gateway = serve_bytes(b"hello world").await;
+        let directory = tempfile::tempdir().unwrap();
+        let mut downloader = Downloader::new(Some(directory.path().display().to_string()));
+        downloader.set_ipfs_gateways(vec![bad_gateway, good_gateway]);
+
+        let book = book_with_md5("5eb63bbbe01eeed093cb22bb8f5acdc3");
+        let cid: Cid = "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG"
+            .parse()
+            .unwrap();
+        let file = downloader.download_from_ipfs(&book, &cid).await.unwrap();
+        assert_eq!(file.md5, book.libgen_md5);
     }
 }
@@ -0,0 +1,239 @@
+use core::fmt;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use crate::{
+    book::LibgenBook,
+    identifiers::LibgenId,
+    queue::write_atomically,
+    scraper::{LibgenClient, LibgenError},
+};
+
+/// Errors from polling the feed or keeping its state
+#[derive(Debug)]
+pub enum FeedError {
+    /// The state file could not be read or written.
+    IOError(String),
+    /// The state file is not valid feed state.
+    ParsingError(String),
+    /// The feed could not be fetched.
+    SearchError(LibgenError),
+}
+
+impl fmt::Display for FeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeedError::IOError(err) => write!(f, "IOError: {}", err),
+            FeedError::ParsingError(err) => write!(f, "ParsingError: {}", err),
+            FeedError::SearchError(err) => write!(f, "SearchError: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for FeedError {}
+
+impl From<std::io::Error> for FeedError {
+    fn from(error: std::io::Error) -> Self {
+        FeedError::IOError(error.to_string())
+    }
+}
+
+impl From<serde_json::Error> for FeedError {
+    fn from(error: serde_json::Error) -> Self {
+        FeedError::ParsingError(error.to_string())
+    }
+}
+
+impl From<LibgenError> for FeedError {
+    fn from(error: LibgenError) -> Self {
+        FeedError::SearchError(error)
+    }
+}
+
+/// Pages one poll reads at most, unless changed with [`RecentFeed::set_max_pages`]
+pub const DEFAULT_MAX_PAGES: u32 = 10;
+
+/// What a poll of the feed found
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedPoll {
+    /// The books added since the last poll, oldest first
+    pub books: Vec<LibgenBook>,
+    /// Whether books may be missing between the last poll and these, as the
+    /// pages read didn't reach back to the high-water mark
+    pub gap: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct FeedState {
+    last_seen_id: Option<LibgenId>,
+}
+
+/// Follows libgens list of latest uploads, handing out each book once
+///
+/// The highest libgen id seen so far is kept in a state file, so polls pick up
+/// where the previous run stopped.
+#[derive(Debug)]
+pub struct RecentFeed {
+    state_path: PathBuf,
+    state: FeedState,
+    max_pages: u32,
+}
+
+impl RecentFeed {
+    /// Opens the feed state at `state_path`, starting fresh when it doesn't exist yet
+    pub async fn load(state_path: impl AsRef<Path>) -> Result<RecentFeed, FeedError> {
+        let state_path = state_path.as_ref().to_path_buf();
+        let state = if fs::try_exists(&state_path).await? {
+            serde_json::from_slice(&fs::read(&state_path).await?)?
+        } else {
+            FeedState::default()
+        };
+        Ok(RecentFeed {
+            state_path,
+            state,
+            max_pages: DEFAULT_MAX_PAGES,
+        })
+    }
+
+    /// Changes how many pages one poll reads at most
+    pub fn set_max_pages(&mut self, pages: u32) {
+        self.max_pages = pages.max(1);
+    }
+
+    /// The highest libgen id handed out so far
+    pub fn high_water_mark(&self) -> Option<LibgenId> {
+        self.state.last_seen_id
+    }
+
+    /// Fetches the feed and returns the books added since the last poll, oldest first
+    ///
+    /// Pages are read until one reaches back to the high-water mark, the first
+    /// poll only reads the first page.
+    pub async fn poll(&mut self, client: &LibgenClient) -> Result<FeedPoll, FeedError> {
+        let mut listed = Vec::new();
+        let mut gap = true;
+        for page in 1..=self.max_pages {
+            let books = client.recently_added_page(page).await?;
+            let reached = match self.state.last_seen_id {
+                None => true,
+                Some(mark) => {
+                    books.is_empty()
+                        || books
+                            .iter()
+                            .any(|book| book.libgen_id.is_some_and(|id| id <= mark))
+                }
+            };
+            listed.extend(books);
+            if reached {
+                gap = false;
+                break;
+            }
+        }
+        Ok(FeedPoll {
+            books: self.take_new(listed).await?,
+            gap,
+        })
+    }
+
+    /// Keeps the books above the high-water mark, oldest first, and raises the mark
+    ///
+    /// The new mark is written to disk before the books are returned.
+    pub async fn take_new(
+        &mut self,
+        listed: Vec<LibgenBook>,
+    ) -> Result<Vec<LibgenBook>, FeedError> {
+        let mut new_books: Vec<LibgenBook> = listed
+            .into_iter()
+            // Books without an id compare below every mark and are left out
+            .filter(|book| book.libgen_id > self.state.last_seen_id)
+            .collect();
+        new_books.sort_by_key(|book| book.libgen_id);
+        new_books.dedup_by_key(|book| book.libgen_id);
+
+        if let Some(newest) = new_books.last() {
+            self.state.last_seen_id = newest.libgen_id;
+            write_atomically(&self.state_path, &serde_json::to_vec_pretty(&self.state)?).await?;
+        }
+        Ok(new_books)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloader::tests::{book_with_md5, serve_bytes, test_client};
+
+    fn book_with_id(id: u64) -> LibgenBook {
+        LibgenBook {
+            libgen_id: Some(LibgenId(id)),
+            ..book_with_md5("5eb63bbbe01eeed093cb22bb8f5acdc3")
+        }
+    }
+
+    #[tokio::test]
+    async fn polls_the_client() {
+        let host = serve_bytes(include_bytes!("../benches/benchmark_page.htm")).await;
+        let client = test_client(&host).build().unwrap();
+        let directory = tempfile::tempdir().unwrap();
+
+        let mut feed = RecentFeed::load(directory.path().join("feed.json"))
+            .await
+            .unwrap();
+        let first = feed.poll(&client).await.unwrap();
+        assert_eq!(first.books.len(), 25);
+        assert!(!first.gap);
+        let second = feed.poll(&client).await.unwrap();
+        assert!(second.books.is_empty());
+        assert!(!second.gap);
+    }
+
+    #[tokio::test]
+    async fn reads_on_until_the_high_water_mark() {
+        let host = serve_bytes(include_bytes!("../benches/benchmark_page.htm")).await;
+        let client = test_client(&host).build().unwrap();
+        let directory = tempfile::tempdir().unwrap();
+        let mut feed = RecentFeed::load(directory.path().join("feed.json"))
+            .await
+            .unwrap();
+        feed.set_max_pages(3);
+
+        // Every page lists books newer than the mark, so it is never reached
+        feed.take_new(vec![book_with_id(12000)]).await.unwrap();
+        let polled = feed.poll(&client).await.unwrap();
+        assert!(polled.gap);
+        assert_eq!(polled.books.len(), 25);
+
+        // The mark was raised to the newest book listed, the first page reaches it
+        assert!(!feed.poll(&client).await.unwrap().gap);
+    }
+
+    #[tokio::test]
+    async fn yields_each_book_once() {
+        let directory = tempfile::tempdir().unwrap();
+        let state_path = directory.path().join("feed.json");
+
+        let mut feed = RecentFeed::load(&state_path).await.unwrap();
+        let first = feed
+            .take_new(vec![book_with_id(12), book_with_id(10)])
+            .await
+            .unwrap();
+        assert_eq!(
+            first
+                .iter()
+                .map(|book| book.libgen_id.unwrap().get())
+                .collect::<Vec<_>>(),
+            vec![10, 12]
+        );
+
+        // A later run only sees what is newer
+        let mut feed = RecentFeed::load(&state_path).await.unwrap();
+        assert_eq!(feed.high_water_mark(), Some(LibgenId(12)));
+        let second = feed
+            .take_new(vec![book_with_id(13), book_with_id(12), book_with_id(11)])
+            .await
+            .unwrap();
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].libgen_id, Some(LibgenId(13)));
+    }
+}
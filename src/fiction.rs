@@ -0,0 +1,429 @@
+use scraper::{ElementRef, Html, Selector};
+use urlencoding::encode;
+
+use crate::{
+    book::{BookDetails, LibgenBook, Section},
+    identifiers::{Isbn, Md5},
+    ipfs::Cid,
+    parser::{ResultParser, SearchColumn},
+    processor::{row_cells, Processor},
+    scraper::LibgenError,
+    util::{parse_authors, parse_md5_from_url},
+};
+
+/// Header labels of the fiction result columns we read
+const AUTHORS_COLUMN: &str = "Author(s)";
+const SERIES_COLUMN: &str = "Series";
+const TITLE_COLUMN: &str = "Title";
+const FILE_COLUMN: &str = "File";
+
+/// What a fiction search matches against
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FictionColumn {
+    /// Title, author and series
+    #[default]
+    All,
+    /// Only the title
+    Title,
+    /// Only the authors
+    Author,
+    /// Only the series
+    Series,
+}
+
+impl FictionColumn {
+    /// The `criteria` parameter of the search
+    fn criteria(self) -> &'static str {
+        match self {
+            FictionColumn::All => "",
+            FictionColumn::Title => "title",
+            FictionColumn::Author => "authors",
+            FictionColumn::Series => "series",
+        }
+    }
+}
+
+/// A search of the fiction section
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FictionQuery {
+    /// The text searched for
+    pub text: String,
+    /// Where the text is looked for
+    pub column: FictionColumn,
+    /// Only books in this language, e.g. `English`
+    pub language: Option<String>,
+    /// Only files with this extension, e.g. `epub`
+    pub format: Option<String>,
+}
+
+impl FictionQuery {
+    /// Searches `text` in titles, authors and series, in every language and format
+    pub fn new(text: &str) -> FictionQuery {
+        FictionQuery {
+            text: text.to_owned(),
+            column: FictionColumn::All,
+            language: None,
+            format: None,
+        }
+    }
+
+    /// Searches by title
+    pub fn title(text: &str) -> FictionQuery {
+        FictionQuery::new(text).column(FictionColumn::Title)
+    }
+
+    /// Searches by author
+    pub fn author(text: &str) -> FictionQuery {
+        FictionQuery::new(text).column(FictionColumn::Author)
+    }
+
+    /// Searches by series
+    pub fn series(text: &str) -> FictionQuery {
+        FictionQuery::new(text).column(FictionColumn::Series)
+    }
+
+    /// Sets where the text is looked for
+    pub fn column(mut self, column: FictionColumn) -> Self {
+        self.column = column;
+        self
+    }
+
+    /// Limits the results to one language
+    pub fn language(mut self, language: &str) -> Self {
+        self.language = Some(language.to_owned());
+        self
+    }
+
+    /// Limits the results to one file format
+    pub fn format(mut self, format: &str) -> Self {
+        self.format = Some(format.to_owned());
+        self
+    }
+
+    /// Path and query of the search, relative to the mirror
+    ///
+    /// This is the `/fiction/` search, whose results are the `table.catalog`
+    /// [`FictionParser`] reads.
+    pub fn path(&self) -> String {
+        format!(
+            "fiction/?q={}&criteria={}&language={}&format={}",
+            encode(&self.text),
+            self.column.criteria(),
+            encode(self.language.as_deref().unwrap_or_default()),
+            encode(self.format.as_deref().unwrap_or_default())
+        )
+    }
+}
+
+/// Positions of the fiction result columns, found from the table header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FictionColumns {
+    authors: usize,
+    series: Option<usize>,
+    title: usize,
+    file: usize,
+}
+
+/// Parses the fiction section, whose results table differs from the main one
+///
+/// Detail and download pages share the main layout and are left to a [`Processor`].
+pub struct FictionParser {
+    /// CSS selector
+    pub results_table_selector: Selector,
+    /// CSS selector
+    pub row_selector: Selector,
+    /// CSS selector
+    pub title_link_selector: Selector,
+    /// CSS selector
+    pub identifier_selector: Selector,
+    /// CSS selector
+    pub author_selector: Selector,
+    processor: Processor,
+}
+
+impl Default for FictionParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FictionParser {
+    /// Creates a new fiction parser with the needed css
+    pub fn new() -> Self {
+        Self {
+            results_table_selector: Selector::parse("table.catalog").unwrap(),
+            row_selector: Selector::parse("tr").unwrap(),
+            title_link_selector: Selector::parse("a[href*='fiction/']").unwrap(),
+            identifier_selector: Selector::parse(".catalog_identifier").unwrap(),
+            author_selector: Selector::parse("a").unwrap(),
+            processor: Processor::new(),
+        }
+    }
+
+    fn parse_header(&self, header: ElementRef<'_>) -> Result<FictionColumns, LibgenError> {
+        let labels: Vec<String> = row_cells(header)
+            .iter()
+            .map(|cell| cell.text().collect::<String>().trim().to_owned())
+            .collect();
+        let position = |name: &str| {
+            labels
+                .iter()
+                .position(|label| label.eq_ignore_ascii_case(name))
+        };
+
+        match (
+            position(AUTHORS_COLUMN),
+            position(TITLE_COLUMN),
+            position(FILE_COLUMN),
+        ) {
+            (Some(authors), Some(title), Some(file)) => Ok(FictionColumns {
+                authors,
+                series: position(SERIES_COLUMN),
+                title,
+                file,
+            }),
+            _ => Err(LibgenError::LayoutChanged(format!(
+                "fiction results header [{}] lacks {}, {} or {}",
+                labels.join(", "),
+                AUTHORS_COLUMN,
+                TITLE_COLUMN,
+                FILE_COLUMN
+            ))),
+        }
+    }
+
+    fn parse_row(&self, columns: &FictionColumns, row: ElementRef<'_>) -> Option<LibgenBook> {
+        let cells = row_cells(row);
+
+        let title_cell = *cells.get(columns.title)?;
+        let title_link = title_cell.select(&self.title_link_selector).next()?;
+        let libgen_md5 = parse_md5_from_url(title_link.value().attr("href")?).ok()?;
+        let title = title_link.text().collect::<String>().trim().to_owned();
+
+        // "EPUB / 310 Kb"
+        let file_type = cells
+            .get(columns.file)?
+            .text()
+            .collect::<String>()
+            .split('/')
+            .next()?
+            .trim()
+            .to_ascii_lowercase();
+
+        let authors = cells
+            .get(columns.authors)?
+            .select(&self.author_selector)
+            .flat_map(|author| parse_authors(&author.text().collect::<String>()))
+            .collect();
+
+        let isbns: Vec<Isbn> = title_cell
+            .select(&self.identifier_selector)
+            .flat_map(|identifier| {
+                identifier
+                    .text()
+                    .collect::<String>()
+                    .trim_start_matches("ISBN:")
+                    .split(',')
+                    .filter_map(|isbn| isbn.parse().ok())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        let series = columns
+            .series
+            .and_then(|series| cells.get(series))
+            .map(|series| series.text().collect::<String>().trim().to_owned())
+            .filter(|series| !series.is_empty());
+
+        Some(LibgenBook {
+            // Not listed on the results page, see `LibgenBook::build_direct_download_url`
+            libgen_id: None,
+            title,
+            authors,
+            publisher: String::new(),
+            libgen_md5,
+            file_type,
+            isbns,
+            year: None,
+            series,
+            section: Section::Fiction,
+        })
+    }
+
+    /// Parses every book on a fiction search page, in page order
+    pub fn search_results_in_document(
+        &self,
+        html_document: &Html,
+    ) -> Result<Vec<LibgenBook>, LibgenError> {
+        let Some(table) = html_document.select(&self.results_table_selector).next() else {
+            if let Some(err) = self.processor.detect_interstitial(html_document) {
+                return Err(err);
+            }
+            // Searches without results leave out the table altogether
+            return Ok(Vec::new());
+        };
+
+        let mut rows = table.select(&self.row_selector);
+        let columns = match rows.next() {
+            Some(header) => self.parse_header(header)?,
+            None => return Ok(Vec::new()),
+        };
+        Ok(rows
+            .filter_map(|row| self.parse_row(&columns, row))
+            .collect())
+    }
+}
+
+impl ResultParser for FictionParser {
+    fn search_path(&self, title: &str) -> String {
+        FictionQuery::title(title).path()
+    }
+
+    fn column_search_path(&self, column: SearchColumn, text: &str) -> String {
+        let column = match column {
+            SearchColumn::Title => FictionColumn::Title,
+            SearchColumn::Author => FictionColumn::Author,
+            SearchColumn::Series => FictionColumn::Series,
+            SearchColumn::Publisher | SearchColumn::Isbn => FictionColumn::All,
+        };
+        FictionQuery::new(text).column(column).path()
+    }
+
+    fn md5_search_path(&self, md5: &Md5) -> String {
+        FictionQuery::new(md5.as_str()).path()
+    }
+
+    fn detail_path(&self, md5: &Md5) -> String {
+        format!("fiction/{}", md5.to_uppercase())
+    }
+
+    fn download_page_path(&self, book: &LibgenBook) -> String {
+        format!("fiction/{}", book.libgen_md5.to_uppercase())
+    }
+
+    fn parse_search_page(
+        &self,
+        html_document: &Html,
+        title: &str,
+    ) -> Result<Option<LibgenBook>, LibgenError> {
+        let title = title.trim().to_ascii_lowercase();
+        Ok(self
+            .search_results_in_document(html_document)?
+            .into_iter()
+            .find(|book| book.title.to_ascii_lowercase().starts_with(&title)))
+    }
+
+    fn parse_search_results(&self, html_document: &Html) -> Result<Vec<LibgenBook>, LibgenError> {
+        self.search_results_in_document(html_document)
+    }
+
+    fn parse_detail_page(&self, html_document: &Html) -> BookDetails {
+        self.processor.parse_detail_page(html_document)
+    }
+
+    fn parse_download_page(&self, html_document: &Html) -> Vec<Cid> {
+        self.processor.parse_ipfs_cids(html_document)
+    }
+
+    fn parse_download_url(&self, html_document: &Html) -> Option<String> {
+        self.processor.parse_download_url(html_document)
+    }
+
+    fn detect_interstitial(&self, html_document: &Html) -> Option<LibgenError> {
+        // The catalog table marks a real fiction page
+        if html_document
+            .select(&self.results_table_selector)
+            .next()
+            .is_some()
+        {
+            return None;
+        }
+        self.processor.detect_interstitial(html_document)
+    }
+
+    fn detect_detail_interstitial(&self, html_document: &Html) -> Option<LibgenError> {
+        self.processor.detect_detail_interstitial(html_document)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{book::Author, parser::Mirror};
+
+    const FICTION_PAGE: &str = r#"<table class="catalog">
+        <thead><tr><th></th><th>Author(s)</th><th>Series</th><th>Title</th><th>Language</th><th>File</th><th>Mirrors</th></tr></thead>
+        <tbody><tr><td></td>
+        <td><ul class="catalog_authors"><li><a href="/fiction/?q=Le+Guin">Le Guin, Ursula K.</a></li></ul></td>
+        <td>Hainish Cycle</td>
+        <td><p><a href="/fiction/2B0A2E0B4F1C4A0E9A3D6C1E8F7B5A41">The Left Hand of Darkness</a></p>
+        <p class="catalog_identifier">ISBN: 9780441478125</p></td>
+        <td>English</td><td title="Uploaded">EPUB / 310 Kb</td>
+        <td><ul class="record_mirrors_compact"><li><a href="http://library.lol/fiction/2B0A2E0B4F1C4A0E9A3D6C1E8F7B5A41">[1]</a></li></ul></td>
+        </tr></tbody></table>"#;
+
+    #[test]
+    fn fiction_query_path() {
+        let query = FictionQuery::author("Le Guin")
+            .language("English")
+            .format("epub");
+        assert_eq!(
+            query.path(),
+            "fiction/?q=Le%20Guin&criteria=authors&language=English&format=epub"
+        );
+        assert_eq!(
+            FictionQuery::new("Earthsea").path(),
+            "fiction/?q=Earthsea&criteria=&language=&format="
+        );
+    }
+
+    #[test]
+    fn parse_fiction_results() {
+        let parser = FictionParser::new();
+        let document = Html::parse_document(FICTION_PAGE);
+
+        let books = parser.search_results_in_document(&document).unwrap();
+        assert_eq!(books.len(), 1);
+        let book = &books[0];
+        assert_eq!(book.title, "The Left Hand of Darkness");
+        assert_eq!(book.libgen_md5.as_str(), "2b0a2e0b4f1c4a0e9a3d6c1e8f7b5a41");
+        assert_eq!(book.authors, vec![Author::new("Le Guin, Ursula K.")]);
+        assert_eq!(book.series.as_deref(), Some("Hainish Cycle"));
+        assert_eq!(book.file_type, "epub");
+        assert_eq!(book.isbns[0].as_str(), "9780441478125");
+        assert_eq!(book.section, Section::Fiction);
+        assert_eq!(book.libgen_id, None);
+
+        assert!(parser
+            .parse_search_page(&document, "the left hand")
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn client_searches_fiction_mirrors() {
+        let host = crate::downloader::tests::serve_bytes(FICTION_PAGE.as_bytes()).await;
+        let client = crate::scraper::LibgenClient::builder()
+            .fiction_mirror(Mirror::new(&host, FictionParser::new()))
+            .build()
+            .unwrap();
+
+        let books = client
+            .search_fiction(&FictionQuery::series("Hainish"))
+            .await
+            .unwrap();
+        assert_eq!(books[0].title, "The Left Hand of Darkness");
+    }
+
+    #[test]
+    fn fiction_layout_changes_are_reported() {
+        let parser = FictionParser::new();
+        let document = Html::parse_document(
+            "<table class=catalog><tr><th>Writer</th><th>Title</th><th>File</th></tr></table>",
+        );
+        assert!(matches!(
+            parser.search_results_in_document(&document),
+            Err(LibgenError::LayoutChanged(_))
+        ));
+    }
+}
@@ -0,0 +1,400 @@
+use core::fmt;
+use std::path::PathBuf;
+
+use crate::book::LibgenBook;
+
+/// Most filesystems limit a single path component to 255 bytes
+pub const MAX_COMPONENT_BYTES: usize = 255;
+
+const RESERVED_WINDOWS_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Errors found while parsing a filename template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    /// A `{placeholder}` that is not supported.
+    UnknownPlaceholder(String),
+    /// A `{` without a matching `}`.
+    UnclosedBrace,
+    /// The template does not produce a file name.
+    MissingFileName,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::UnknownPlaceholder(name) => write!(f, "UnknownPlaceholder: {}", name),
+            TemplateError::UnclosedBrace => write!(f, "UnclosedBrace"),
+            TemplateError::MissingFileName => write!(f, "MissingFileName"),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// Book values a template can refer to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    Title,
+    Authors,
+    Author,
+    Year,
+    Series,
+    Publisher,
+    Md5,
+    Id,
+    Ext,
+}
+
+impl Placeholder {
+    fn parse(name: &str) -> Result<Placeholder, TemplateError> {
+        Ok(match name {
+            "title" => Placeholder::Title,
+            "authors" => Placeholder::Authors,
+            "author" => Placeholder::Author,
+            "year" => Placeholder::Year,
+            "series" => Placeholder::Series,
+            "publisher" => Placeholder::Publisher,
+            "md5" => Placeholder::Md5,
+            "id" => Placeholder::Id,
+            "ext" => Placeholder::Ext,
+            _ => return Err(TemplateError::UnknownPlaceholder(name.to_owned())),
+        })
+    }
+
+    fn value(self, book: &LibgenBook) -> String {
+        match self {
+            Placeholder::Title => book.title.clone(),
+            Placeholder::Authors => book
+                .authors
+                .iter()
+                .map(|author| author.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+            Placeholder::Author => book
+                .authors
+                .first()
+                .map(|author| author.name.clone())
+                .unwrap_or_default(),
+            Placeholder::Year => book.year.map(|year| year.to_string()).unwrap_or_default(),
+            Placeholder::Series => book.series.clone().unwrap_or_default(),
+            Placeholder::Publisher => book.publisher.clone(),
+            Placeholder::Md5 => book.libgen_md5.to_string(),
+            Placeholder::Id => book.libgen_id.map(|id| id.to_string()).unwrap_or_default(),
+            Placeholder::Ext => book.file_type.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Value(Placeholder),
+}
+
+/// A pattern for naming downloaded books.
+///
+/// Placeholders are written in braces, `{title}`, `{authors}`, `{author}` (the first
+/// author), `{year}`, `{series}`, `{publisher}`, `{md5}`, `{id}` and `{ext}`. A `/`
+/// starts a subdirectory, directories that render empty are skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilenameTemplate {
+    /// Every path component, the last one is the file name
+    components: Vec<Vec<Segment>>,
+}
+
+impl Default for FilenameTemplate {
+    fn default() -> Self {
+        FilenameTemplate::new("{title}.{ext}").unwrap()
+    }
+}
+
+impl FilenameTemplate {
+    /// Parses a template such as `{authors} - {title} ({year}).{ext}`
+    pub fn new(template: &str) -> Result<FilenameTemplate, TemplateError> {
+        let mut components = Vec::new();
+        for component in template.split(['/', '\\']) {
+            let mut segments = Vec::new();
+            let mut rest = component;
+            while let Some(open) = rest.find('{') {
+                if open > 0 {
+                    segments.push(Segment::Literal(rest[..open].to_owned()));
+                }
+                let close = rest[open..].find('}').ok_or(TemplateError::UnclosedBrace)?;
+                let name = &rest[open + 1..open + close];
+                segments.push(Segment::Value(Placeholder::parse(name.trim())?));
+                rest = &rest[open + close + 1..];
+            }
+            if !rest.is_empty() {
+                segments.push(Segment::Literal(rest.to_owned()));
+            }
+            components.push(segments);
+        }
+
+        // A trailing slash leaves an empty file name
+        if components.last().is_none_or(Vec::is_empty) {
+            return Err(TemplateError::MissingFileName);
+        }
+        Ok(FilenameTemplate { components })
+    }
+
+    /// Renders the relative path a book should be saved to
+    pub fn render(&self, book: &LibgenBook) -> PathBuf {
+        let (file_name, directories) = self.components.split_last().unwrap();
+
+        let mut path = PathBuf::new();
+        for directory in directories {
+            let rendered = tidy_rendered(&render_segments(directory, book));
+            if !rendered.is_empty() {
+                path.push(sanitize_file_name(&rendered, MAX_COMPONENT_BYTES));
+            }
+        }
+        let rendered = tidy_rendered(&render_segments(file_name, book));
+        path.push(sanitize_file_name(&rendered, MAX_COMPONENT_BYTES));
+        path
+    }
+}
+
+/// Renders one path component
+///
+/// Brackets around a value that renders empty are dropped along with the
+/// space before them, so `{title} ({year})` becomes just the title.
+fn render_segments(segments: &[Segment], book: &LibgenBook) -> String {
+    let mut rendered = String::new();
+    let mut unclosed = None;
+    for (index, segment) in segments.iter().enumerate() {
+        match segment {
+            Segment::Literal(text) => match unclosed.take() {
+                Some(close) => rendered.push_str(text.strip_prefix(close).unwrap_or(text)),
+                None => rendered.push_str(text),
+            },
+            Segment::Value(placeholder) => {
+                // Values can't introduce their own directories
+                let value = placeholder.value(book).replace(['/', '\\'], "_");
+                if let Some(close) = bracket_around(segments, index).filter(|_| value.is_empty()) {
+                    rendered.pop();
+                    rendered.truncate(rendered.trim_end().len());
+                    unclosed = Some(close);
+                }
+                rendered.push_str(&value);
+            }
+        }
+    }
+    rendered
+}
+
+/// The closing bracket when the value at `index` sits right inside `()` or `[]`
+fn bracket_around(segments: &[Segment], index: usize) -> Option<char> {
+    let (Some(Segment::Literal(before)), Some(Segment::Literal(after))) = (
+        index.checked_sub(1).map(|before| &segments[before]),
+        segments.get(index + 1),
+    ) else {
+        return None;
+    };
+    [('(', ')'), ('[', ']')]
+        .into_iter()
+        .find(|&(open, close)| before.ends_with(open) && after.starts_with(close))
+        .map(|(_, close)| close)
+}
+
+/// Collapses runs of whitespace
+fn tidy_rendered(rendered: &str) -> String {
+    rendered.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Makes a single path component safe to use on Windows, macOS and Linux.
+///
+/// Reserved and control characters become `_`, trailing dots and spaces are
+/// trimmed, reserved Windows device names such as `CON` or `NUL` are prefixed with
+/// `_`, and the result is truncated to `max_bytes` while keeping the extension and
+/// UTF-8 character boundaries intact.
+///
+/// # Examples
+///
+/// ```
+/// use libgen_scraper::filename::sanitize_file_name;
+/// assert_eq!(sanitize_file_name("a: b?.pdf", 255), "a_ b_.pdf");
+/// assert_eq!(sanitize_file_name("nul.txt", 255), "_nul.txt");
+/// assert_eq!(sanitize_file_name("ééé.pdf", 8), "éé.pdf");
+/// ```
+pub fn sanitize_file_name(name: &str, max_bytes: usize) -> String {
+    let replaced: String = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let mut cleaned = replaced
+        .trim_start()
+        .trim_end_matches(['.', ' '])
+        .to_owned();
+
+    if cleaned.is_empty() {
+        cleaned.push('_');
+    }
+
+    let stem = cleaned.split('.').next().unwrap_or_default().trim_end();
+    if RESERVED_WINDOWS_NAMES
+        .iter()
+        .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+    {
+        cleaned.insert(0, '_');
+    }
+
+    truncate_keeping_extension(&cleaned, max_bytes)
+}
+
+fn truncate_keeping_extension(name: &str, max_bytes: usize) -> String {
+    if name.len() <= max_bytes {
+        return name.to_owned();
+    }
+
+    let (stem, extension) = match name.rfind('.') {
+        // Only treat short suffixes as extensions
+        Some(dot) if dot > 0 && name.len() - dot <= 16 && name.len() - dot < max_bytes => {
+            name.split_at(dot)
+        }
+        _ => (name, ""),
+    };
+
+    let mut end = max_bytes - extension.len();
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    let stem = stem[..end].trim_end_matches(['.', ' ']);
+    format!("{}{}", stem, extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        book::{Author, Section},
+        identifiers::{LibgenId, Md5},
+    };
+
+    fn test_book() -> LibgenBook {
+        LibgenBook {
+            libgen_id: Some(LibgenId(12091)),
+            libgen_md5: "E75FACEC3020926608936CB68FEE8066".parse::<Md5>().unwrap(),
+            file_type: "djvu".to_owned(),
+            title: "Benchmarking: Temporal Distribution?".to_owned(),
+            authors: vec![
+                Author::new("Estela Bee Dagum"),
+                Author::new("Pierre A. Cholette"),
+            ],
+            publisher: "Springer-Verlag New York".to_owned(),
+            isbns: vec![],
+            year: Some(2006),
+            series: None,
+            section: Section::NonFiction,
+        }
+    }
+
+    #[test]
+    fn default_template() {
+        let path = FilenameTemplate::default().render(&test_book());
+        assert_eq!(
+            path,
+            PathBuf::from("Benchmarking_ Temporal Distribution_.djvu")
+        );
+    }
+
+    #[test]
+    fn authors_title_year_template() {
+        let template = FilenameTemplate::new("{authors} - {title} ({year}).{ext}").unwrap();
+        assert_eq!(
+            template.render(&test_book()),
+            PathBuf::from(
+                "Estela Bee Dagum, Pierre A. Cholette - Benchmarking_ Temporal Distribution_ (2006).djvu"
+            )
+        );
+    }
+
+    #[test]
+    fn subdirectories_skip_missing_values() {
+        let template = FilenameTemplate::new("{author}/{series}/{md5}.{ext}").unwrap();
+        assert_eq!(
+            template.render(&test_book()),
+            PathBuf::from("Estela Bee Dagum").join("e75facec3020926608936cb68fee8066.djvu")
+        );
+    }
+
+    #[test]
+    fn unknown_ids_render_empty() {
+        let mut book = test_book();
+        let template = FilenameTemplate::new("{id}/{md5}.{ext}").unwrap();
+        assert_eq!(
+            template.render(&book),
+            PathBuf::from("12091").join("e75facec3020926608936cb68fee8066.djvu")
+        );
+        book.libgen_id = None;
+        assert_eq!(
+            template.render(&book),
+            PathBuf::from("e75facec3020926608936cb68fee8066.djvu")
+        );
+    }
+
+    #[test]
+    fn missing_year_brackets_removed() {
+        let mut book = test_book();
+        book.year = None;
+        let template = FilenameTemplate::new("{md5} ({year}).{ext}").unwrap();
+        assert_eq!(
+            template.render(&book),
+            PathBuf::from("e75facec3020926608936cb68fee8066.djvu")
+        );
+    }
+
+    #[test]
+    fn literal_brackets_are_kept() {
+        let template = FilenameTemplate::new("() {md5} [{series}] ({year}) [] .{ext}").unwrap();
+        assert_eq!(
+            template.render(&test_book()),
+            PathBuf::from("() e75facec3020926608936cb68fee8066 (2006) [] .djvu")
+        );
+    }
+
+    #[test]
+    fn template_errors() {
+        assert_eq!(
+            FilenameTemplate::new("{isbn}.{ext}"),
+            Err(TemplateError::UnknownPlaceholder("isbn".to_owned()))
+        );
+        assert_eq!(
+            FilenameTemplate::new("{title.{ext}"),
+            Err(TemplateError::UnknownPlaceholder("title.{ext".to_owned()))
+        );
+        assert_eq!(
+            FilenameTemplate::new("{title"),
+            Err(TemplateError::UnclosedBrace)
+        );
+        assert_eq!(
+            FilenameTemplate::new("{author}/"),
+            Err(TemplateError::MissingFileName)
+        );
+    }
+
+    #[test]
+    fn sanitize_reserved_and_trailing() {
+        assert_eq!(sanitize_file_name("CON", 255), "_CON");
+        assert_eq!(sanitize_file_name("com1.pdf", 255), "_com1.pdf");
+        assert_eq!(sanitize_file_name("console.pdf", 255), "console.pdf");
+        assert_eq!(sanitize_file_name("title. . ", 255), "title");
+        assert_eq!(sanitize_file_name("a\u{7}b\nc", 255), "a_b_c");
+        assert_eq!(sanitize_file_name("...", 255), "_");
+    }
+
+    #[test]
+    fn sanitize_truncates_on_char_boundary() {
+        let long = format!("{}.epub", "日本".repeat(100));
+        let truncated = sanitize_file_name(&long, MAX_COMPONENT_BYTES);
+        assert!(truncated.len() <= MAX_COMPONENT_BYTES);
+        assert!(truncated.ends_with(".epub"));
+        assert!(truncated.starts_with("日本日本"));
+    }
+}
@@ -0,0 +1,375 @@
+use core::fmt;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::util::calculate_group_id;
+
+/// Errors raised when a string is not a valid identifier
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdentifierError {
+    /// Not 32 hexadecimal characters.
+    InvalidMd5(String),
+    /// Not a positive integer.
+    InvalidLibgenId(String),
+    /// Wrong length, bad characters or a failing checksum.
+    InvalidIsbn(String),
+    /// Not a CIDv0 or base32 CIDv1.
+    InvalidCid(String),
+    /// Not a `10.<registrant>/<suffix>` DOI.
+    InvalidDoi(String),
+}
+
+impl fmt::Display for IdentifierError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IdentifierError::InvalidMd5(value) => write!(f, "InvalidMd5: {}", value),
+            IdentifierError::InvalidLibgenId(value) => write!(f, "InvalidLibgenId: {}", value),
+            IdentifierError::InvalidIsbn(value) => write!(f, "InvalidIsbn: {}", value),
+            IdentifierError::InvalidCid(value) => write!(f, "InvalidCid: {}", value),
+            IdentifierError::InvalidDoi(value) => write!(f, "InvalidDoi: {}", value),
+        }
+    }
+}
+
+impl std::error::Error for IdentifierError {}
+
+/// A validated MD5 hash, always stored in lowercase.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Md5(String);
+
+impl Md5 {
+    /// The lowercase hex string
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The uppercase hex string, as used in most libgen links
+    pub fn to_uppercase(&self) -> String {
+        self.0.to_ascii_uppercase()
+    }
+}
+
+impl FromStr for Md5 {
+    type Err = IdentifierError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        if trimmed.len() == 32 && trimmed.bytes().all(|b| b.is_ascii_hexdigit()) {
+            Ok(Md5(trimmed.to_ascii_lowercase()))
+        } else {
+            Err(IdentifierError::InvalidMd5(s.to_owned()))
+        }
+    }
+}
+
+impl TryFrom<String> for Md5 {
+    type Error = IdentifierError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Md5> for String {
+    fn from(md5: Md5) -> Self {
+        md5.0
+    }
+}
+
+impl fmt::Display for Md5 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// The numeric id libgen assigns to every book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct LibgenId(pub u64);
+
+impl LibgenId {
+    /// The raw id
+    pub fn get(self) -> u64 {
+        self.0
+    }
+
+    /// The thousand-block this book is stored under, see [`calculate_group_id`]
+    pub fn group_id(self) -> u64 {
+        calculate_group_id(self.0)
+    }
+}
+
+impl From<u64> for LibgenId {
+    fn from(id: u64) -> Self {
+        LibgenId(id)
+    }
+}
+
+impl FromStr for LibgenId {
+    type Err = IdentifierError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.trim()
+            .parse::<u64>()
+            .map(LibgenId)
+            .map_err(|_| IdentifierError::InvalidLibgenId(s.to_owned()))
+    }
+}
+
+impl fmt::Display for LibgenId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A checksum validated ISBN-10 or ISBN-13, stored without hyphens.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Isbn(String);
+
+impl Isbn {
+    /// The normalized digits, an ISBN-10 may end in `X`
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether this is a 13 digit ISBN
+    pub fn is_isbn13(&self) -> bool {
+        self.0.len() == 13
+    }
+
+    /// Converts to ISBN-13, ISBN-10s get the `978` prefix
+    pub fn to_isbn13(&self) -> Isbn {
+        if self.is_isbn13() {
+            return self.clone();
+        }
+        let mut digits = format!("978{}", &self.0[..9]);
+        digits.push(isbn13_check_digit(&digits));
+        Isbn(digits)
+    }
+
+    /// Converts to ISBN-10, only possible for ISBN-13s starting with `978`
+    pub fn to_isbn10(&self) -> Option<Isbn> {
+        if !self.is_isbn13() {
+            return Some(self.clone());
+        }
+        let body = self.0.strip_prefix("978")?;
+        let mut digits = body[..9].to_owned();
+        digits.push(isbn10_check_digit(&digits));
+        Some(Isbn(digits))
+    }
+}
+
+fn isbn10_check_digit(first_nine: &str) -> char {
+    let sum: u32 = first_nine
+        .bytes()
+        .zip((2..=10).rev())
+        .map(|(b, weight)| u32::from(b - b'0') * weight)
+        .sum();
+    match (11 - sum % 11) % 11 {
+        10 => 'X',
+        digit => char::from_digit(digit, 10).unwrap(),
+    }
+}
+
+fn isbn13_check_digit(first_twelve: &str) -> char {
+    let sum: u32 = first_twelve
+        .bytes()
+        .enumerate()
+        .map(|(i, b)| u32::from(b - b'0') * if i % 2 == 0 { 1 } else { 3 })
+        .sum();
+    char::from_digit((10 - sum % 10) % 10, 10).unwrap()
+}
+
+impl FromStr for Isbn {
+    type Err = IdentifierError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || IdentifierError::InvalidIsbn(s.to_owned());
+        let digits: String = s
+            .trim()
+            .chars()
+            .filter(|c| *c != '-' && *c != ' ')
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+        // Only ASCII is accepted, so the length below counts characters
+        if !digits.chars().all(|c| c.is_ascii_digit() || c == 'X') {
+            return Err(error());
+        }
+
+        let (body, check) = match digits.len() {
+            10 | 13 => digits.split_at(digits.len() - 1),
+            _ => return Err(error()),
+        };
+        if !body.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(error());
+        }
+
+        let expected = if digits.len() == 10 {
+            isbn10_check_digit(body)
+        } else {
+            isbn13_check_digit(body)
+        };
+        if check.starts_with(expected) {
+            Ok(Isbn(digits))
+        } else {
+            Err(error())
+        }
+    }
+}
+
+impl TryFrom<String> for Isbn {
+    type Error = IdentifierError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Isbn> for String {
+    fn from(isbn: Isbn) -> Self {
+        isbn.0
+    }
+}
+
+impl fmt::Display for Isbn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A digital object identifier, e.g. `10.1038/nature12373`.
+///
+/// `doi:` and `https://doi.org/` prefixes are removed. DOIs are case insensitive
+/// and kept in lowercase.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Doi(String);
+
+impl Doi {
+    /// The DOI without any prefix
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Doi {
+    type Err = IdentifierError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        let doi = [
+            "https://doi.org/",
+            "http://doi.org/",
+            "https://dx.doi.org/",
+            "doi:",
+        ]
+        .iter()
+        .find_map(|prefix| {
+            trimmed
+                .get(..prefix.len())
+                .filter(|start| start.eq_ignore_ascii_case(prefix))
+                .map(|_| &trimmed[prefix.len()..])
+        })
+        .unwrap_or(trimmed)
+        .trim();
+
+        let valid = doi.split_once('/').is_some_and(|(prefix, suffix)| {
+            prefix.strip_prefix("10.").is_some_and(|registrant| {
+                !registrant.is_empty() && registrant.chars().all(|c| c.is_ascii_digit() || c == '.')
+            }) && !suffix.is_empty()
+                && !suffix.chars().any(char::is_whitespace)
+        });
+        if valid {
+            Ok(Doi(doi.to_lowercase()))
+        } else {
+            Err(IdentifierError::InvalidDoi(s.to_owned()))
+        }
+    }
+}
+
+impl TryFrom<String> for Doi {
+    type Error = IdentifierError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Doi> for String {
+    fn from(doi: Doi) -> Self {
+        doi.0
+    }
+}
+
+impl fmt::Display for Doi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn md5_is_lowercased() {
+        let md5: Md5 = "E75FACEC3020926608936CB68FEE8066".parse().unwrap();
+        assert_eq!(md5.as_str(), "e75facec3020926608936cb68fee8066");
+    }
+
+    #[test]
+    fn md5_rejects_bad_input() {
+        assert!("abcde".parse::<Md5>().is_err());
+        assert!("e75facec3020926608936cb68fee806g".parse::<Md5>().is_err());
+    }
+
+    #[test]
+    fn md5_deserialize_validates() {
+        let md5: Md5 = serde_json::from_str("\"E75FACEC3020926608936CB68FEE8066\"").unwrap();
+        assert_eq!(md5.as_str(), "e75facec3020926608936cb68fee8066");
+        assert!(serde_json::from_str::<Md5>("\"abcde\"").is_err());
+    }
+
+    #[test]
+    fn libgen_id_group() {
+        let id: LibgenId = "3759134".parse().unwrap();
+        assert_eq!(id.group_id(), 3759000);
+    }
+
+    #[test]
+    fn isbn_checksums() {
+        assert!("0387311025".parse::<Isbn>().is_ok());
+        assert!("978-0-387-31102-9".parse::<Isbn>().is_ok());
+        assert!("0387311026".parse::<Isbn>().is_err());
+        assert!("9780387311020".parse::<Isbn>().is_err());
+        assert!("080442957X".parse::<Isbn>().is_ok());
+    }
+
+    #[test]
+    fn isbn_rejects_non_ascii() {
+        assert!("12345678é".parse::<Isbn>().is_err());
+        assert!("038731102é".parse::<Isbn>().is_err());
+        assert!("９780387311029".parse::<Isbn>().is_err());
+    }
+
+    #[test]
+    fn doi_prefixes_and_case() {
+        let doi: Doi = "https://doi.org/10.1038/Nature12373".parse().unwrap();
+        assert_eq!(doi.as_str(), "10.1038/nature12373");
+        assert_eq!("doi:10.1038/nature12373".parse::<Doi>().unwrap(), doi);
+        assert!("10.1038".parse::<Doi>().is_err());
+        assert!("11.1038/nature12373".parse::<Doi>().is_err());
+    }
+
+    #[test]
+    fn isbn_conversion() {
+        let isbn10: Isbn = "0849336228".parse().unwrap();
+        let isbn13: Isbn = "9780849336225".parse().unwrap();
+        assert_eq!(isbn10.to_isbn13(), isbn13);
+        assert_eq!(isbn13.to_isbn10(), Some(isbn10));
+        let isbn979: Isbn = "9791034304011".parse().unwrap();
+        assert_eq!(isbn979.to_isbn10(), None);
+    }
+}
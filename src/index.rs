@@ -0,0 +1,684 @@
+use md5::{Digest, Md5 as Md5Hasher};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::SeekFrom,
+    ops::Bound,
+    path::{Path, PathBuf},
+};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+
+use crate::{
+    book::LibgenBook,
+    catalog::{CatalogError, CatalogRecord, LocalCatalog},
+    identifiers::{Isbn, Md5},
+    queue::write_atomically,
+};
+
+/// How many hits a search returns unless told otherwise
+pub const DEFAULT_LIMIT: usize = 25;
+
+/// Bytes hashed at either end of the indexed catalog to recognise it
+const FINGERPRINT_BYTES: u64 = 4096;
+
+/// The index is saved once this part of the catalog was indexed since the last save
+const SAVE_FRACTION: u64 = 8;
+
+/// A field of a book the index knows about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum IndexField {
+    /// Book title
+    Title,
+    /// Author names
+    Author,
+    /// Series name
+    Series,
+    /// Publisher
+    Publisher,
+    /// ISBNs, as ISBN-13
+    Isbn,
+}
+
+impl IndexField {
+    /// Every field, in declaration order so `field as u8` indexes it
+    const ALL: [IndexField; 5] = [
+        IndexField::Title,
+        IndexField::Author,
+        IndexField::Series,
+        IndexField::Publisher,
+        IndexField::Isbn,
+    ];
+
+    /// How much a match in this field counts towards the ranking
+    fn weight(self) -> f32 {
+        match self {
+            IndexField::Title => 3.0,
+            IndexField::Author => 2.0,
+            IndexField::Series => 1.5,
+            IndexField::Publisher => 1.0,
+            IndexField::Isbn => 4.0,
+        }
+    }
+}
+
+/// A search of the [`CatalogIndex`]
+///
+/// Every word has to match, in any of the searched fields.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexQuery {
+    /// The words searched for
+    pub text: String,
+    /// Fields searched, all of them when empty
+    pub fields: Vec<IndexField>,
+    /// Whether words also match terms they are the start of, e.g. while typing
+    pub prefix: bool,
+    /// Whether words also match terms a typo or two away
+    pub fuzzy: bool,
+    /// Most hits returned
+    pub limit: usize,
+}
+
+impl IndexQuery {
+    /// Searches `text` in every field, exact words only
+    pub fn new(text: &str) -> IndexQuery {
+        IndexQuery {
+            text: text.to_owned(),
+            fields: Vec::new(),
+            prefix: false,
+            fuzzy: false,
+            limit: DEFAULT_LIMIT,
+        }
+    }
+
+    /// Only searches `field`, can be given more than once
+    pub fn field(mut self, field: IndexField) -> Self {
+        self.fields.push(field);
+        self
+    }
+
+    /// Lets words match the start of longer terms
+    pub fn prefix(mut self) -> Self {
+        self.prefix = true;
+        self
+    }
+
+    /// Lets words match despite typos
+    pub fn fuzzy(mut self) -> Self {
+        self.fuzzy = true;
+        self
+    }
+
+    /// Returns at most `limit` hits
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    fn searches(&self, field: IndexField) -> bool {
+        self.fields.is_empty() || self.fields.contains(&field)
+    }
+}
+
+/// A book found in the index
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    /// The book
+    pub book: LibgenBook,
+    /// Language of the book
+    pub language: Option<String>,
+    /// How well it matched, higher is better
+    pub score: f32,
+}
+
+/// Saved as a `[document, field]` pair, there is one for every word of every book
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "(u32, u8)", into = "(u32, u8)")]
+struct Posting {
+    document: u32,
+    field: IndexField,
+}
+
+impl From<Posting> for (u32, u8) {
+    fn from(posting: Posting) -> Self {
+        (posting.document, posting.field as u8)
+    }
+}
+
+impl TryFrom<(u32, u8)> for Posting {
+    type Error = String;
+
+    fn try_from((document, field): (u32, u8)) -> Result<Self, Self::Error> {
+        let field = *IndexField::ALL
+            .get(field as usize)
+            .ok_or_else(|| format!("unknown index field {}", field))?;
+        Ok(Posting { document, field })
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct IndexState {
+    /// Bytes of the catalog already indexed
+    indexed_bytes: u64,
+    /// See [`fingerprint`], tells a rebuilt catalog from a grown one
+    #[serde(default)]
+    fingerprint: Option<String>,
+    /// Where each document starts in the catalog, `None` once a later record replaced it
+    documents: Vec<Option<u64>>,
+    /// The current document of each book
+    md5s: HashMap<Md5, u32>,
+    /// Sorted, so prefixes are a range
+    terms: BTreeMap<String, Vec<Posting>>,
+}
+
+/// An inverted index over a [`LocalCatalog`], for searches that don't read the whole
+/// catalog
+///
+/// The catalog only ever grows, so [`CatalogIndex::update`] indexes what was
+/// appended since, whether by [`LocalCatalog::import`] or [`LocalCatalog::add_books`].
+/// A record for a book already indexed replaces the earlier one.
+///
+/// The whole index is held in memory while it is open. Saving writes all of it,
+/// so updates only save once a sizeable part of the catalog is new. What wasn't
+/// saved is indexed again by the next update after loading, [`CatalogIndex::save`]
+/// saves right away.
+pub struct CatalogIndex {
+    path: PathBuf,
+    state: IndexState,
+    /// `indexed_bytes` of the saved index
+    saved_bytes: u64,
+}
+
+impl CatalogIndex {
+    /// Opens the index at `path`, empty when the file doesn't exist yet
+    pub async fn load(path: impl AsRef<Path>) -> Result<CatalogIndex, CatalogError> {
+        let path = path.as_ref().to_path_buf();
+        let state = if fs::try_exists(&path).await? {
+            serde_json::from_slice(&fs::read(&path).await?)?
+        } else {
+            IndexState::default()
+        };
+        Ok(CatalogIndex {
+            path,
+            saved_bytes: state.indexed_bytes,
+            state,
+        })
+    }
+
+    /// Books in the index
+    pub fn len(&self) -> usize {
+        self.state.md5s.len()
+    }
+
+    /// Whether nothing was indexed yet
+    pub fn is_empty(&self) -> bool {
+        self.state.md5s.is_empty()
+    }
+
+    /// Indexes the records added to `catalog` since the last update
+    ///
+    /// Starts over when the catalog was replaced, i.e. got shorter or its indexed
+    /// part changed. Returns how many records were indexed.
+    pub async fn update(&mut self, catalog: &LocalCatalog) -> Result<usize, CatalogError> {
+        let catalog_bytes = fs::metadata(catalog.path()).await?.len();
+        if catalog_bytes < self.state.indexed_bytes
+            || self.state.fingerprint
+                != fingerprint(catalog.path(), self.state.indexed_bytes).await?
+        {
+            self.state = IndexState::default();
+            self.saved_bytes = 0;
+        }
+
+        let mut indexed = 0;
+        let state = &mut self.state;
+        state.indexed_bytes = catalog
+            .scan_from(state.indexed_bytes, |offset, record| {
+                state.insert(offset, &record);
+                indexed += 1;
+            })
+            .await?;
+        self.state.fingerprint = fingerprint(catalog.path(), self.state.indexed_bytes).await?;
+
+        let unsaved = self.state.indexed_bytes - self.saved_bytes;
+        if unsaved > 0 && unsaved >= self.state.indexed_bytes / SAVE_FRACTION {
+            self.save().await?;
+        }
+        Ok(indexed)
+    }
+
+    /// Writes the index to its file
+    pub async fn save(&mut self) -> Result<(), CatalogError> {
+        write_atomically(&self.path, &serde_json::to_vec(&self.state)?).await?;
+        self.saved_bytes = self.state.indexed_bytes;
+        Ok(())
+    }
+
+    /// Ranks the indexed books against `query`, best first
+    ///
+    /// The books themselves are read from `catalog`, which has to be the one indexed.
+    /// Fails with [`CatalogError::StaleIndex`] when a record isn't the book indexed
+    /// at its place.
+    pub async fn search(
+        &self,
+        catalog: &LocalCatalog,
+        query: &IndexQuery,
+    ) -> Result<Vec<SearchHit>, CatalogError> {
+        let mut hits = Vec::new();
+        for (document, score) in self.state.rank(query) {
+            let Some(offset) = self.state.documents[document as usize] else {
+                continue;
+            };
+            let CatalogRecord { book, language, .. } = catalog.record_at(offset).await?;
+            if self.state.md5s.get(&book.libgen_md5) != Some(&document) {
+                return Err(CatalogError::StaleIndex);
+            }
+            hits.push(SearchHit {
+                book,
+                language,
+                score,
+            });
+        }
+        Ok(hits)
+    }
+}
+
+impl IndexState {
+    fn insert(&mut self, offset: u64, record: &CatalogRecord) {
+        let book = &record.book;
+        let document = self.documents.len() as u32;
+        self.documents.push(Some(offset));
+        if let Some(replaced) = self.md5s.insert(book.libgen_md5.clone(), document) {
+            self.documents[replaced as usize] = None;
+        }
+
+        let authors: Vec<&str> = book
+            .authors
+            .iter()
+            .map(|author| author.name.as_str())
+            .collect();
+        let fields = [
+            (IndexField::Title, tokenize(&book.title)),
+            (IndexField::Author, tokenize(&authors.join(" "))),
+            (
+                IndexField::Series,
+                tokenize(book.series.as_deref().unwrap_or_default()),
+            ),
+            (IndexField::Publisher, tokenize(&book.publisher)),
+            (
+                IndexField::Isbn,
+                book.isbns
+                    .iter()
+                    .map(|isbn| isbn.to_isbn13().to_string())
+                    .collect(),
+            ),
+        ];
+        for (field, terms) in fields {
+            for term in terms {
+                let postings = self.terms.entry(term).or_default();
+                let posting = Posting { document, field };
+                if postings.last() != Some(&posting) {
+                    postings.push(posting);
+                }
+            }
+        }
+    }
+
+    /// Scores live documents matching every word of the query, best first
+    fn rank(&self, query: &IndexQuery) -> Vec<(u32, f32)> {
+        let words = query_terms(&query.text);
+        if words.is_empty() {
+            return Vec::new();
+        }
+
+        let mut scores: Option<HashMap<u32, f32>> = None;
+        for word in &words {
+            let mut word_scores: HashMap<u32, f32> = HashMap::new();
+            for (term, quality) in self.expand(word, query) {
+                let postings = &self.terms[term];
+                let rarity = (1.0 + self.md5s.len() as f32 / postings.len() as f32).ln();
+                for posting in postings {
+                    if !query.searches(posting.field)
+                        || self.documents[posting.document as usize].is_none()
+                    {
+                        continue;
+                    }
+                    let score = posting.field.weight() * quality * rarity;
+                    let best = word_scores.entry(posting.document).or_default();
+                    *best = best.max(score);
+                }
+            }
+
+            scores = Some(match scores {
+                None => word_scores,
+                Some(mut scores) => {
+                    scores.retain(|document, score| match word_scores.get(document) {
+                        Some(word_score) => {
+                            *score += word_score;
+                            true
+                        }
+                        None => false,
+                    });
+                    scores
+                }
+            });
+        }
+
+        let mut ranked: Vec<(u32, f32)> = scores.unwrap_or_default().into_iter().collect();
+        // Ties go to the newer record
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(b.0.cmp(&a.0)));
+        ranked.truncate(query.limit);
+        ranked
+    }
+
+    /// Index terms a query word matches, with how well each matches
+    fn expand<'a>(&'a self, word: &str, query: &IndexQuery) -> Vec<(&'a String, f32)> {
+        let mut matches: HashMap<&String, f32> = HashMap::new();
+        if let Some((term, _)) = self.terms.get_key_value(word) {
+            matches.insert(term, 1.0);
+        }
+        if query.prefix {
+            for (term, _) in self
+                .terms
+                .range::<str, _>((Bound::Included(word), Bound::Unbounded))
+                .take_while(|(term, _)| term.starts_with(word))
+            {
+                matches.entry(term).or_insert(0.7);
+            }
+        }
+        let max_typos = match word.chars().count() {
+            0..=3 => 0,
+            4..=7 => 1,
+            _ => 2,
+        };
+        if query.fuzzy && max_typos > 0 {
+            // Typos in the first letter are rare, so only terms sharing it are compared
+            let first = &word[..word.chars().next().map_or(0, char::len_utf8)];
+            for (term, _) in self
+                .terms
+                .range::<str, _>((Bound::Included(first), Bound::Unbounded))
+                .take_while(|(term, _)| term.starts_with(first))
+            {
+                if !matches.contains_key(term) && within_distance(word, term, max_typos) {
+                    matches.insert(term, 0.5);
+                }
+            }
+        }
+        matches.into_iter().collect()
+    }
+}
+
+/// MD5 of the first and last [`FINGERPRINT_BYTES`] of the catalogs first `length` bytes
+///
+/// `None` while nothing is indexed.
+async fn fingerprint(catalog: &Path, length: u64) -> Result<Option<String>, CatalogError> {
+    if length == 0 {
+        return Ok(None);
+    }
+    let mut file = fs::File::open(catalog).await?;
+    let mut buffer = vec![0; FINGERPRINT_BYTES.min(length) as usize];
+    let mut hasher = Md5Hasher::new();
+    file.read_exact(&mut buffer).await?;
+    hasher.update(&buffer);
+    file.seek(SeekFrom::Start(length - buffer.len() as u64))
+        .await?;
+    file.read_exact(&mut buffer).await?;
+    hasher.update(&buffer);
+    Ok(Some(
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect(),
+    ))
+}
+
+/// Lowercased words, split at anything that isn't a letter or digit
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Like [`tokenize`], but ISBNs in either form become the ISBN-13 the index holds
+fn query_terms(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .flat_map(|word| match word.parse::<Isbn>() {
+            Ok(isbn) => vec![isbn.to_isbn13().to_string()],
+            Err(_) => tokenize(word),
+        })
+        .collect()
+}
+
+/// Whether `a` turns into `b` with at most `limit` insertions, deletions or substitutions
+fn within_distance(a: &str, b: &str, limit: usize) -> bool {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > limit {
+        return false;
+    }
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        if current.iter().min().is_some_and(|closest| *closest > limit) {
+            return false;
+        }
+        previous = current;
+    }
+    previous[b.len()] <= limit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloader::tests::book_with_md5;
+
+    fn book(md5: &str, title: &str, year: u16) -> LibgenBook {
+        LibgenBook {
+            title: title.to_owned(),
+            year: Some(year),
+            ..book_with_md5(md5)
+        }
+    }
+
+    #[test]
+    fn tokenizes_words_and_isbns() {
+        assert_eq!(
+            tokenize("Cats: a Short-History (2nd ed.)"),
+            vec!["cats", "a", "short", "history", "2nd", "ed"]
+        );
+        assert_eq!(
+            query_terms("0471609226 cats"),
+            vec!["9780471609223".to_owned(), "cats".to_owned()]
+        );
+        assert!(within_distance("categroies", "categories", 2));
+        assert!(!within_distance("cats", "dogs", 1));
+    }
+
+    #[test]
+    fn postings_are_saved_as_pairs() {
+        let posting = Posting {
+            document: 7,
+            field: IndexField::Series,
+        };
+        let json = serde_json::to_string(&posting).unwrap();
+        assert_eq!(json, "[7,2]");
+        assert_eq!(serde_json::from_str::<Posting>(&json).unwrap(), posting);
+        assert!(serde_json::from_str::<Posting>("[7,9]").is_err());
+    }
+
+    #[tokio::test]
+    async fn searches_and_updates_incrementally() {
+        let directory = tempfile::tempdir().unwrap();
+        let catalog = LocalCatalog::new(directory.path().join("catalog.jsonl"));
+        catalog
+            .add_books(&[
+                book(
+                    "5fa82be26689a4e6f4415ea068d35a9d",
+                    "Abstract and concrete categories",
+                    1990,
+                ),
+                book(
+                    "5eb63bbbe01eeed093cb22bb8f5acdc3",
+                    "Categories for cats",
+                    2004,
+                ),
+            ])
+            .await
+            .unwrap();
+
+        let index_path = directory.path().join("index.json");
+        let mut index = CatalogIndex::load(&index_path).await.unwrap();
+        assert_eq!(index.update(&catalog).await.unwrap(), 2);
+        assert_eq!(index.update(&catalog).await.unwrap(), 0);
+
+        let titles = |hits: Vec<SearchHit>| -> Vec<String> {
+            hits.into_iter().map(|hit| hit.book.title).collect()
+        };
+        assert_eq!(
+            titles(
+                index
+                    .search(&catalog, &IndexQuery::new("categories cats"))
+                    .await
+                    .unwrap()
+            ),
+            vec!["Categories for cats"]
+        );
+        assert_eq!(
+            index
+                .search(&catalog, &IndexQuery::new("categ"))
+                .await
+                .unwrap(),
+            vec![]
+        );
+        assert_eq!(
+            index
+                .search(&catalog, &IndexQuery::new("categ").prefix())
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(
+            titles(
+                index
+                    .search(&catalog, &IndexQuery::new("abstrakt").fuzzy())
+                    .await
+                    .unwrap()
+            ),
+            vec!["Abstract and concrete categories"]
+        );
+        assert!(index
+            .search(&catalog, &IndexQuery::new("bstract").fuzzy())
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(index
+            .search(
+                &catalog,
+                &IndexQuery::new("categories").field(IndexField::Author)
+            )
+            .await
+            .unwrap()
+            .is_empty());
+
+        // A newer record of a known book replaces it, a reloaded index picks up there
+        catalog
+            .add_books(&[book(
+                "5eb63bbbe01eeed093cb22bb8f5acdc3",
+                "Categories for dogs",
+                2005,
+            )])
+            .await
+            .unwrap();
+        let mut index = CatalogIndex::load(&index_path).await.unwrap();
+        assert_eq!(index.update(&catalog).await.unwrap(), 1);
+        assert_eq!(index.len(), 2);
+        assert!(index
+            .search(&catalog, &IndexQuery::new("cats"))
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            titles(
+                index
+                    .search(&catalog, &IndexQuery::new("categories"))
+                    .await
+                    .unwrap()
+            ),
+            vec!["Categories for dogs", "Abstract and concrete categories"]
+        );
+    }
+
+    #[tokio::test]
+    async fn starts_over_for_a_rebuilt_catalog() {
+        let directory = tempfile::tempdir().unwrap();
+        let catalog = LocalCatalog::new(directory.path().join("catalog.jsonl"));
+        catalog
+            .add_books(&[book("5fa82be26689a4e6f4415ea068d35a9d", "Cats", 1990)])
+            .await
+            .unwrap();
+        let mut index = CatalogIndex::load(directory.path().join("index.json"))
+            .await
+            .unwrap();
+        index.update(&catalog).await.unwrap();
+
+        // Replaced by a longer catalog, the old offsets mean nothing
+        std::fs::remove_file(catalog.path()).unwrap();
+        catalog
+            .add_books(&[
+                book("5eb63bbbe01eeed093cb22bb8f5acdc3", "Dogs", 2004),
+                book("00000000000000000000000000000000", "More dogs", 2005),
+            ])
+            .await
+            .unwrap();
+        assert!(matches!(
+            index.search(&catalog, &IndexQuery::new("cats")).await,
+            Err(CatalogError::StaleIndex)
+        ));
+        assert_eq!(index.update(&catalog).await.unwrap(), 2);
+        assert_eq!(index.len(), 2);
+        assert_eq!(
+            index
+                .search(&catalog, &IndexQuery::new("dogs"))
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn small_updates_are_saved_later() {
+        let directory = tempfile::tempdir().unwrap();
+        let catalog = LocalCatalog::new(directory.path().join("catalog.jsonl"));
+        let index_path = directory.path().join("index.json");
+        let books: Vec<LibgenBook> = (0..16)
+            .map(|n| book(&format!("{:032x}", n), "Cats", 1990))
+            .collect();
+        catalog.add_books(&books).await.unwrap();
+        let mut index = CatalogIndex::load(&index_path).await.unwrap();
+        index.update(&catalog).await.unwrap();
+
+        catalog
+            .add_books(&[book("5eb63bbbe01eeed093cb22bb8f5acdc3", "Dogs", 2004)])
+            .await
+            .unwrap();
+        assert_eq!(index.update(&catalog).await.unwrap(), 1);
+        // Not saved yet, so a reloaded index indexes the new record again
+        let mut reloaded = CatalogIndex::load(&index_path).await.unwrap();
+        assert_eq!(reloaded.update(&catalog).await.unwrap(), 1);
+
+        index.save().await.unwrap();
+        let mut reloaded = CatalogIndex::load(&index_path).await.unwrap();
+        assert_eq!(reloaded.update(&catalog).await.unwrap(), 0);
+        assert_eq!(reloaded.len(), 17);
+    }
+}
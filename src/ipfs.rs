@@ -0,0 +1,97 @@
+use core::fmt;
+use std::str::FromStr;
+
+use crate::identifiers::IdentifierError;
+
+/// Public gateways listed on library.lol that still serve files, after a local node
+pub const DEFAULT_IPFS_GATEWAYS: [&str; 3] = [
+    "http://127.0.0.1:8080",
+    "https://ipfs.io",
+    "https://gateway.pinata.cloud",
+];
+
+const BASE58_ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// An IPFS content id, either a base58 CIDv0 (`Qm...`) or a base32 CIDv1 (`bafy...`).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Cid(String);
+
+impl Cid {
+    /// The CID as text
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The url of this CID on a gateway, e.g. `https://ipfs.io/ipfs/<cid>`
+    pub fn gateway_url(&self, gateway: &str) -> String {
+        format!("{}/ipfs/{}", gateway.trim_end_matches('/'), self.0)
+    }
+
+    /// Finds the CID in a gateway link such as `https://ipfs.io/ipfs/<cid>?filename=..`
+    pub fn from_gateway_url(url: &str) -> Option<Cid> {
+        let (_, rest) = url.split_once("/ipfs/")?;
+        let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        rest[..end].parse().ok()
+    }
+}
+
+impl FromStr for Cid {
+    type Err = IdentifierError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let cid = s.trim();
+        let is_v0 = cid.len() == 46
+            && cid.starts_with("Qm")
+            && cid.chars().all(|c| BASE58_ALPHABET.contains(c));
+        // Multibase prefix 'b' followed by lowercase base32
+        let is_v1 = cid.len() > 50
+            && cid.starts_with('b')
+            && cid
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || (b'2'..=b'7').contains(&b));
+
+        if is_v0 || is_v1 {
+            Ok(Cid(cid.to_owned()))
+        } else {
+            Err(IdentifierError::InvalidCid(s.to_owned()))
+        }
+    }
+}
+
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CID_V1: &str = "bafykbzacedtnd2yrnb4ue4dqu3ri7fxclj3ttiq3bibdyhb6pm5fv7zpefbxy";
+
+    #[test]
+    fn parses_cid_versions() {
+        assert!(CID_V1.parse::<Cid>().is_ok());
+        assert!("QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG"
+            .parse::<Cid>()
+            .is_ok());
+        assert!("Qm0000".parse::<Cid>().is_err());
+        assert!("bafyUPPERCASE".parse::<Cid>().is_err());
+    }
+
+    #[test]
+    fn cid_from_gateway_url() {
+        let url = format!(
+            "https://cloudflare-ipfs.com/ipfs/{}?filename=book.pdf",
+            CID_V1
+        );
+        let cid = Cid::from_gateway_url(&url).unwrap();
+        assert_eq!(cid.as_str(), CID_V1);
+        assert_eq!(
+            cid.gateway_url("http://127.0.0.1:8080/"),
+            format!("http://127.0.0.1:8080/ipfs/{}", CID_V1)
+        );
+        assert_eq!(Cid::from_gateway_url("https://library.lol/main/abc"), None);
+    }
+}
@@ -15,14 +15,48 @@
 //!
 #![warn(missing_docs)]
 
+/// Libgen JSON API
+pub mod api;
+/// Download rate limiting
+pub mod bandwidth;
 /// Book module
 pub mod book;
+/// On-disk cache of fetched pages
+pub mod cache;
+/// Offline search in libgens database dumps
+pub mod catalog;
+/// Following libgens latest uploads
+pub mod feed;
+/// Fiction section search
+pub mod fiction;
+/// Download filename templates
+pub mod filename;
+/// Md5, libgen id and ISBN types
+pub mod identifiers;
+/// Full-text index over the local catalog
+pub mod index;
+/// IPFS content ids and gateways
+pub mod ipfs;
+/// Site layouts and the parsers for them
+pub mod parser;
 /// CSS Selectors
 pub mod processor;
+/// HTTP(S), SOCKS5 and Tor proxy configuration
+pub mod proxy;
+/// Download queue with retries and resumable state
+pub mod queue;
+/// Scientific articles from the scimag collection
+pub mod scimag;
 /// HTML libgen scraper
 pub mod scraper;
+/// Interchangeable search backends and chains of them
+pub mod source;
+/// Locating books in libgens repository torrents
+pub mod torrent;
 /// One off methods
 pub mod util;
+/// Saved queries and the watcher re-running them
+pub mod wishlist;
 
 /// Downloader!!
 pub mod downloader;
@@ -0,0 +1,123 @@
+use core::fmt;
+use std::sync::Arc;
+
+use scraper::Html;
+
+use crate::{
+    book::{BookDetails, LibgenBook},
+    identifiers::Md5,
+    ipfs::Cid,
+    scraper::LibgenError,
+};
+
+/// The field a search matches against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SearchColumn {
+    /// Book title
+    Title,
+    /// Author names
+    Author,
+    /// Series name
+    Series,
+    /// Publisher
+    Publisher,
+    /// ISBNs and other identifiers
+    Isbn,
+}
+
+/// Knows the urls and markup of one kind of libgen site
+///
+/// [`crate::processor::Processor`] handles libgen.rs, .is and .st. Sites with
+/// other markup, like libgen.li, get their own implementation.
+pub trait ResultParser: Send + Sync {
+    /// Path and query of a title search, relative to the mirror
+    fn search_path(&self, title: &str) -> String;
+
+    /// Path and query of a search in one column, relative to the mirror
+    fn column_search_path(&self, column: SearchColumn, text: &str) -> String;
+
+    /// Path and query of a search for one MD5, relative to the mirror
+    ///
+    /// Sites without one search for the MD5 as a title, results are checked
+    /// against it either way.
+    fn md5_search_path(&self, md5: &Md5) -> String {
+        self.search_path(md5.as_str())
+    }
+
+    /// Path of a books detail page, relative to the mirror
+    fn detail_path(&self, md5: &Md5) -> String;
+
+    /// Path of a books download page, relative to the mirror
+    fn download_page_path(&self, book: &LibgenBook) -> String;
+
+    /// Finds the book matching `title` on a search page
+    fn parse_search_page(
+        &self,
+        html_document: &Html,
+        title: &str,
+    ) -> Result<Option<LibgenBook>, LibgenError>;
+
+    /// Parses every book on a search page, in page order
+    fn parse_search_results(&self, html_document: &Html) -> Result<Vec<LibgenBook>, LibgenError>;
+
+    /// Reads the exact size and hashes from a detail page
+    fn parse_detail_page(&self, html_document: &Html) -> BookDetails;
+
+    /// Collects the IPFS CIDs linked from a download page
+    fn parse_download_page(&self, html_document: &Html) -> Vec<Cid>;
+
+    /// The direct file link on a download page, if the site has one
+    fn parse_download_url(&self, _html_document: &Html) -> Option<String> {
+        None
+    }
+
+    /// Recognises challenge and rate limit pages served instead of the real one
+    fn detect_interstitial(&self, _html_document: &Html) -> Option<LibgenError> {
+        None
+    }
+
+    /// Like [`ResultParser::detect_interstitial`], for detail pages
+    fn detect_detail_interstitial(&self, html_document: &Html) -> Option<LibgenError> {
+        self.detect_interstitial(html_document)
+    }
+}
+
+/// A site together with the parser for its markup
+#[derive(Clone)]
+pub struct Mirror {
+    base_url: String,
+    parser: Arc<dyn ResultParser>,
+}
+
+impl Mirror {
+    /// A mirror at `base_url`, e.g. `https://libgen.li`
+    pub fn new(base_url: &str, parser: impl ResultParser + 'static) -> Mirror {
+        Mirror {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            parser: Arc::new(parser),
+        }
+    }
+
+    /// The url the mirror was created with
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// The parser for this mirrors pages
+    pub fn parser(&self) -> &dyn ResultParser {
+        self.parser.as_ref()
+    }
+
+    /// An absolute url for a path on this mirror
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path.trim_start_matches('/'))
+    }
+}
+
+impl fmt::Debug for Mirror {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mirror")
+            .field("base_url", &self.base_url)
+            .finish_non_exhaustive()
+    }
+}
@@ -1,100 +1,540 @@
-use crate::{book::LibgenBook, scraper::LibgenError, util::parse_md5_from_url};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::{
+    book::{BookDetails, LibgenBook, Section},
+    identifiers::{Isbn, LibgenId, Md5},
+    ipfs::Cid,
+    parser::{ResultParser, SearchColumn},
+    scraper::LibgenError,
+    util::{parse_authors, parse_md5_from_url},
+};
 use scraper::{ElementRef, Html, Selector};
+use urlencoding::encode;
+
+lazy_static! {
+    static ref SIZE_IN_BYTES: Regex = Regex::new(r"\(([\d\s,]+) bytes\)").unwrap();
+}
+
+/// Longest page text, in bytes, still read as a rate limit notice
+const NOTICE_LENGTH_LIMIT: usize = 2000;
+
+/// Widest `colspan` we honour, browsers clamp to the same
+const MAX_COLSPAN: usize = 1000;
+
+/// Header labels of the search result columns we read
+const ID_COLUMN: &str = "ID";
+const AUTHORS_COLUMN: &str = "Author(s)";
+const TITLE_COLUMN: &str = "Title";
+const PUBLISHER_COLUMN: &str = "Publisher";
+const YEAR_COLUMN: &str = "Year";
+const EXTENSION_COLUMN: &str = "Extension";
+
+/// Positions of the search result columns, found from the table header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResultColumns {
+    /// Libgen id
+    pub id: usize,
+    /// Author(s)
+    pub authors: usize,
+    /// Title, with series, edition and ISBNs
+    pub title: usize,
+    /// Publisher
+    pub publisher: usize,
+    /// Year
+    pub year: usize,
+    /// File extension
+    pub extension: usize,
+}
+
+/// The cells directly in a table row, not those of nested tables
+pub(crate) fn row_cells(row: ElementRef<'_>) -> Vec<ElementRef<'_>> {
+    row.children()
+        .filter_map(ElementRef::wrap)
+        .filter(|cell| matches!(cell.value().name(), "td" | "th"))
+        .collect()
+}
 
 /// A html processor to grab needed elements
 pub struct Processor {
     /// CSS selector
-    pub book_libgen_id_selector: Selector,
+    pub book_authors_selector: Selector,
     /// CSS selector
-    pub book_publisher_selector: Selector,
+    pub book_search_result_selector: Selector,
     /// CSS selector
-    pub book_file_type_selector: Selector,
+    pub book_isbn_selector: Selector,
     /// CSS selector
-    pub book_authors_selector: Selector,
+    pub book_series_selector: Selector,
     /// CSS selector
-    pub book_search_result_selector: Selector,
+    pub download_page_link_selector: Selector,
+    /// CSS selector
+    pub download_url_selector: Selector,
+    /// CSS selector
+    pub results_table_selector: Selector,
+    /// CSS selector
+    pub page_title_selector: Selector,
+    /// CSS selector
+    pub challenge_selector: Selector,
+    /// CSS selector
+    pub heading_selector: Selector,
+    /// CSS selector
+    pub detail_row_selector: Selector,
+    /// CSS selector
+    pub detail_cell_selector: Selector,
+}
+
+impl Default for Processor {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Processor {
     /// Creates a new html processor with the needed css
     pub fn new() -> Self {
         Self {
-            book_libgen_id_selector: Selector::parse("td:first-child").unwrap(),
-            book_publisher_selector: Selector::parse("td:nth-child(4)").unwrap(),
-            book_file_type_selector: Selector::parse("td:nth-child(9)").unwrap(),
-            book_authors_selector: Selector::parse("td:nth-child(2) > a:not([title])").unwrap(),
-            book_search_result_selector: Selector::parse("table.c tbody tr").unwrap(),
+            book_authors_selector: Selector::parse("a:not([title])").unwrap(),
+            book_search_result_selector: Selector::parse("tr").unwrap(),
+            book_isbn_selector: Selector::parse("font > i").unwrap(),
+            download_page_link_selector: Selector::parse("#download a[href]").unwrap(),
+            download_url_selector: Selector::parse("#download h2 a[href]").unwrap(),
+            results_table_selector: Selector::parse("table.c").unwrap(),
+            page_title_selector: Selector::parse("title").unwrap(),
+            heading_selector: Selector::parse("title, h1").unwrap(),
+            challenge_selector: Selector::parse(
+                "#challenge-form, #cf-wrapper, .cf-browser-verification, #challenge-running, \
+                 script[src*='challenge-platform'], .g-recaptcha, .h-captcha, iframe[src*='captcha']",
+            )
+            .unwrap(),
+            detail_row_selector: Selector::parse("tr").unwrap(),
+            detail_cell_selector: Selector::parse("th, td").unwrap(),
+            book_series_selector: Selector::parse("a[href*='column=series']").unwrap(),
+        }
+    }
+
+    /// Maps the columns of a search results table by their header text
+    ///
+    /// Fails with [`LibgenError::LayoutChanged`] naming the missing columns when
+    /// the header no longer has the ones we read.
+    pub fn parse_results_header(
+        &self,
+        table: ElementRef<'_>,
+    ) -> Result<ResultColumns, LibgenError> {
+        let header = table
+            .select(&self.book_search_result_selector)
+            .next()
+            .ok_or_else(|| LibgenError::LayoutChanged("results table has no rows".to_owned()))?;
+
+        // Cells spanning several columns, like Mirrors, shift the ones after them
+        let mut labels: Vec<String> = Vec::new();
+        for cell in row_cells(header) {
+            let label = cell.text().collect::<String>().trim().to_owned();
+            let span = cell
+                .value()
+                .attr("colspan")
+                .and_then(|span| span.parse().ok())
+                .unwrap_or(1usize);
+            labels.extend(std::iter::repeat_n(label, span.clamp(1, MAX_COLSPAN)));
+        }
+
+        let mut missing = Vec::new();
+        let mut position = |name: &'static str| {
+            labels
+                .iter()
+                .position(|label| label.eq_ignore_ascii_case(name))
+                .unwrap_or_else(|| {
+                    missing.push(name);
+                    0
+                })
+        };
+        let columns = ResultColumns {
+            id: position(ID_COLUMN),
+            authors: position(AUTHORS_COLUMN),
+            title: position(TITLE_COLUMN),
+            publisher: position(PUBLISHER_COLUMN),
+            year: position(YEAR_COLUMN),
+            extension: position(EXTENSION_COLUMN),
+        };
+
+        if missing.is_empty() {
+            Ok(columns)
+        } else {
+            labels.dedup();
+            Err(LibgenError::LayoutChanged(format!(
+                "missing columns {} in results header [{}]",
+                missing.join(", "),
+                labels.join(", ")
+            )))
         }
     }
 
     /// Parses the html from a search result on libgen
-    fn parse_search_result(&self, title: &str, result_row: ElementRef<'_>) -> Option<LibgenBook> {
-        let book_id_elem = result_row.select(&self.book_libgen_id_selector).next()?;
+    ///
+    /// With a `title`, rows whose title doesn't start with it are skipped.
+    fn parse_search_result(
+        &self,
+        title: Option<&str>,
+        columns: &ResultColumns,
+        result_row: ElementRef<'_>,
+    ) -> Option<LibgenBook> {
+        let cells = row_cells(result_row);
 
-        let libgen_id = book_id_elem.inner_html().parse::<u64>().ok()?;
+        let libgen_id = cells
+            .get(columns.id)?
+            .inner_html()
+            .trim()
+            .parse::<LibgenId>()
+            .ok()?;
 
         // CSS to grab the title of a search result
-        let title_cell_selector =
-            Selector::parse(&format!("td[width='500'] > a[id='{}']", libgen_id)).unwrap();
+        let title_cell_selector = Selector::parse(&format!("a[id='{}']", libgen_id)).unwrap();
 
-        let title_cell = result_row.select(&title_cell_selector).next()?;
+        let title_column = *cells.get(columns.title)?;
+        let title_cell = title_column.select(&title_cell_selector).next()?;
 
-        let search_result_title = title_cell.text().nth(0)?.trim();
+        let search_result_title = title_cell.text().next()?.trim();
 
         // If the search result title doesnt contain/match the title parameter return none. We know it isn't the correct book
         // If two books end up with the same title, whichever is processed first is returned
         // TODO: add advanced search
         let search_result_title_trimmed = search_result_title.trim();
-        let title_trimmed = title.trim();
-        if !search_result_title_trimmed
-            .to_ascii_lowercase()
-            .starts_with(&title_trimmed.to_ascii_lowercase())
-        {
-            return None;
+        if let Some(title) = title {
+            let title_trimmed = title.trim();
+            if !search_result_title_trimmed
+                .to_ascii_lowercase()
+                .starts_with(&title_trimmed.to_ascii_lowercase())
+            {
+                return None;
+            }
         }
         // TODO: Alternate path, going to the book download page on libgen and grabbin the url there instead of skipping it (since we are creating the direct link from the info on the search page).
-        let file_type: String = result_row
-            .select(&self.book_file_type_selector)
-            .next()
-            .unwrap()
-            .inner_html();
+        let file_type: String = cells.get(columns.extension)?.inner_html();
 
-        let href_book_link: String = title_cell.value().attr("href")?.to_string();
+        let href_book_link = title_cell.value().attr("href")?;
 
-        let authors: Vec<_> = result_row
+        // Text nodes are entity-decoded, unlike inner_html
+        let authors: Vec<_> = cells
+            .get(columns.authors)?
             .select(&self.book_authors_selector)
-            .into_iter()
-            .map(|auth| auth.inner_html())
+            .flat_map(|auth| parse_authors(&auth.text().collect::<String>()))
+            .collect();
+
+        // The edition shares the same markup, it just fails to parse as an ISBN
+        let isbns: Vec<Isbn> = title_cell
+            .select(&self.book_isbn_selector)
+            .flat_map(|italic| {
+                italic
+                    .text()
+                    .collect::<String>()
+                    .split(',')
+                    .filter_map(|isbn| isbn.parse().ok())
+                    .collect::<Vec<_>>()
+            })
             .collect();
 
-        let publisher = result_row
-            .select(&self.book_publisher_selector)
+        let publisher = cells.get(columns.publisher)?.inner_html();
+
+        let year = cells
+            .get(columns.year)
+            .and_then(|year| year.inner_html().trim().parse::<u16>().ok());
+
+        let series = title_column
+            .select(&self.book_series_selector)
             .next()
-            .unwrap()
-            .inner_html();
+            .map(|series| series.text().collect::<String>().trim().to_owned())
+            .filter(|series| !series.is_empty());
 
         Some(LibgenBook {
             title: search_result_title.to_owned(),
-            libgen_id,
-            libgen_md5: parse_md5_from_url(href_book_link)?,
+            libgen_id: Some(libgen_id),
+            libgen_md5: parse_md5_from_url(href_book_link).ok()?,
             publisher,
             authors,
             file_type,
+            isbns,
+            year,
+            series,
+            section: Section::NonFiction,
         })
     }
 
+    /// Collects the IPFS CIDs linked from a library.lol download page
+    pub fn parse_ipfs_cids(&self, html_document: &Html) -> Vec<Cid> {
+        let mut cids: Vec<Cid> = Vec::new();
+        for link in html_document.select(&self.download_page_link_selector) {
+            if let Some(cid) = link.value().attr("href").and_then(Cid::from_gateway_url) {
+                // Every gateway links the same CID
+                if !cids.contains(&cid) {
+                    cids.push(cid);
+                }
+            }
+        }
+        cids
+    }
+
+    /// Parses the exact size and file hashes from a books detail page
+    pub fn parse_detail_page(&self, html_document: &Html) -> BookDetails {
+        let mut details = BookDetails::default();
+
+        // The hashes sit in label/value rows, the label cell is either a th or td
+        for row in html_document.select(&self.detail_row_selector) {
+            let mut cells = row.select(&self.detail_cell_selector);
+            let (Some(label), Some(value)) = (cells.next(), cells.next()) else {
+                continue;
+            };
+            let label = label.text().collect::<String>();
+            let value = value.text().collect::<String>().trim().to_owned();
+            if value.is_empty() {
+                continue;
+            }
+            let field = match label
+                .trim()
+                .trim_end_matches(':')
+                .to_ascii_uppercase()
+                .as_str()
+            {
+                "BTIH" => &mut details.btih,
+                "TTH" => &mut details.tth,
+                "SHA1" => &mut details.sha1,
+                "SHA256" => &mut details.sha256,
+                _ => continue,
+            };
+            field.get_or_insert(value);
+        }
+
+        let text = html_document.root_element().text().collect::<String>();
+        details.size_bytes = SIZE_IN_BYTES.captures(&text).and_then(|captures| {
+            captures[1]
+                .chars()
+                .filter(char::is_ascii_digit)
+                .collect::<String>()
+                .parse()
+                .ok()
+        });
+        details
+    }
+
+    /// Recognises pages served instead of the requested one, such as Cloudflare
+    /// challenges, captchas and "Too many requests" notices
+    ///
+    /// These often come back with status 200, and would otherwise look like a
+    /// search without results.
+    pub fn detect_interstitial(&self, html_document: &Html) -> Option<LibgenError> {
+        // A real results table means a real page, whatever the book titles say
+        if html_document
+            .select(&self.results_table_selector)
+            .next()
+            .is_some()
+        {
+            return None;
+        }
+        self.detect_blocked_page(html_document)
+    }
+
+    /// Whether a page is a books detail page, recognised by its hash rows
+    pub fn is_detail_page(&self, html_document: &Html) -> bool {
+        html_document.select(&self.detail_row_selector).any(|row| {
+            row.select(&self.detail_cell_selector)
+                .next()
+                .is_some_and(|label| {
+                    matches!(
+                        label
+                            .text()
+                            .collect::<String>()
+                            .trim()
+                            .trim_end_matches(':')
+                            .to_ascii_uppercase()
+                            .as_str(),
+                        "MD5" | "BTIH" | "TTH" | "SHA1" | "SHA256"
+                    )
+                })
+        })
+    }
+
+    /// Recognises challenge and rate limit pages, whichever page was asked for
+    ///
+    /// Rate limit wording only counts in the title or a heading of a short page,
+    /// real pages quote it in book titles and descriptions.
+    pub fn detect_blocked_page(&self, html_document: &Html) -> Option<LibgenError> {
+        if html_document
+            .select(&self.challenge_selector)
+            .next()
+            .is_some()
+        {
+            return Some(LibgenError::ChallengeError);
+        }
+
+        let title = html_document
+            .select(&self.page_title_selector)
+            .next()
+            .map(|title| title.text().collect::<String>().to_ascii_lowercase())
+            .unwrap_or_default();
+        if title.contains("just a moment")
+            || title.contains("attention required")
+            || title.contains("captcha")
+        {
+            return Some(LibgenError::ChallengeError);
+        }
+
+        let page_length: usize = html_document
+            .root_element()
+            .text()
+            .map(|text| text.trim().len())
+            .sum();
+        if page_length > NOTICE_LENGTH_LIMIT {
+            return None;
+        }
+        let headings = html_document
+            .select(&self.heading_selector)
+            .map(|heading| heading.text().collect::<String>().to_ascii_lowercase())
+            .collect::<Vec<_>>()
+            .join(" ");
+        if headings.contains("too many requests") || headings.contains("rate limit") {
+            return Some(LibgenError::RateLimitedError);
+        }
+        None
+    }
+
+    /// Finds the results table and its columns
+    ///
+    /// Challenge and rate limit pages, and pages whose results table is missing or
+    /// changed shape, are reported as errors.
+    fn results_table<'a>(
+        &self,
+        html_document: &'a Html,
+    ) -> Result<(ElementRef<'a>, ResultColumns), LibgenError> {
+        if let Some(err) = self.detect_interstitial(html_document) {
+            return Err(err);
+        }
+        let table = html_document
+            .select(&self.results_table_selector)
+            .next()
+            .ok_or_else(|| LibgenError::LayoutChanged("results table not found".to_owned()))?;
+        let columns = self.parse_results_header(table)?;
+        Ok((table, columns))
+    }
+
     /// Looks for a books title in the html reponse
+    ///
+    /// Challenge and rate limit pages, and pages whose results table is missing or
+    /// changed shape, are reported as errors rather than as `None`.
     pub fn search_title_in_document(
         &self,
         html_document: &Html,
         title: &str,
     ) -> Result<Option<LibgenBook>, LibgenError> {
-        let book_data = html_document
+        let (table, columns) = self.results_table(html_document)?;
+
+        let book_data = table
             .select(&self.book_search_result_selector)
-            .find_map(|srch_result| self.parse_search_result(title, srch_result));
+            .skip(1)
+            .find_map(|srch_result| self.parse_search_result(Some(title), &columns, srch_result));
 
         Ok(book_data)
     }
+
+    /// Parses every book on a search page, in page order
+    pub fn search_results_in_document(
+        &self,
+        html_document: &Html,
+    ) -> Result<Vec<LibgenBook>, LibgenError> {
+        let (table, columns) = self.results_table(html_document)?;
+
+        Ok(table
+            .select(&self.book_search_result_selector)
+            .skip(1)
+            .filter_map(|srch_result| self.parse_search_result(None, &columns, srch_result))
+            .collect())
+    }
+
+    /// The direct link behind the GET button of a library.lol download page
+    pub fn parse_download_url(&self, html_document: &Html) -> Option<String> {
+        html_document
+            .select(&self.download_url_selector)
+            .next()
+            .and_then(|link| link.value().attr("href"))
+            .map(str::to_owned)
+    }
+}
+
+/// The layout of libgen.rs, .is and .st, and of the library.lol download pages
+impl ResultParser for Processor {
+    fn search_path(&self, title: &str) -> String {
+        format!(
+            "search.php?&req={}&phrase=1&view=simple&column=title&sort=year&sortmode=DESC",
+            encode(title)
+        )
+    }
+
+    fn column_search_path(&self, column: SearchColumn, text: &str) -> String {
+        let column = match column {
+            SearchColumn::Title => "title",
+            SearchColumn::Author => "author",
+            SearchColumn::Series => "series",
+            SearchColumn::Publisher => "publisher",
+            SearchColumn::Isbn => "identifier",
+        };
+        format!(
+            "search.php?&req={}&phrase=1&view=simple&column={}&sort=year&sortmode=DESC",
+            encode(text),
+            column
+        )
+    }
+
+    fn md5_search_path(&self, md5: &Md5) -> String {
+        format!(
+            "search.php?&req={}&phrase=1&view=simple&column=md5",
+            md5.to_uppercase()
+        )
+    }
+
+    fn detail_path(&self, md5: &Md5) -> String {
+        format!("book/index.php?md5={}", md5.to_uppercase())
+    }
+
+    fn download_page_path(&self, book: &LibgenBook) -> String {
+        let section = match book.section {
+            Section::NonFiction => "main",
+            Section::Fiction => "fiction",
+            Section::Scimag => "scimag",
+        };
+        format!("{}/{}", section, book.libgen_md5.to_uppercase())
+    }
+
+    fn parse_search_page(
+        &self,
+        html_document: &Html,
+        title: &str,
+    ) -> Result<Option<LibgenBook>, LibgenError> {
+        self.search_title_in_document(html_document, title)
+    }
+
+    fn parse_search_results(&self, html_document: &Html) -> Result<Vec<LibgenBook>, LibgenError> {
+        self.search_results_in_document(html_document)
+    }
+
+    fn parse_detail_page(&self, html_document: &Html) -> BookDetails {
+        Processor::parse_detail_page(self, html_document)
+    }
+
+    fn parse_download_page(&self, html_document: &Html) -> Vec<Cid> {
+        self.parse_ipfs_cids(html_document)
+    }
+
+    fn parse_download_url(&self, html_document: &Html) -> Option<String> {
+        Processor::parse_download_url(self, html_document)
+    }
+
+    fn detect_interstitial(&self, html_document: &Html) -> Option<LibgenError> {
+        Processor::detect_interstitial(self, html_document)
+    }
+
+    fn detect_detail_interstitial(&self, html_document: &Html) -> Option<LibgenError> {
+        if self.is_detail_page(html_document) {
+            return None;
+        }
+        self.detect_blocked_page(html_document)
+    }
 }
 
 #[cfg(test)]
@@ -102,6 +542,7 @@ mod tests {
     use std::fs;
 
     use super::*;
+    use crate::book::{Author, AuthorRole};
 
     #[test]
     fn parse_result_existing_title() {
@@ -128,6 +569,248 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_result_structured_authors() {
+        let client_processor = Processor::new();
+        let html_content = fs::read_to_string("benches/benchmark_page.htm").unwrap();
+        let document = Html::parse_document(&html_content);
+
+        let book = client_processor
+            .search_title_in_document(&document, "Benchmarking, Temporal Distribution")
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(book.year, Some(2006));
+        assert_eq!(
+            book.series.as_deref(),
+            Some("Lecture Notes in Statistics 186")
+        );
+        assert_eq!(
+            book.authors,
+            vec![
+                Author::new("Estela Bee Dagum"),
+                Author {
+                    name: "Pierre A. Cholette".to_string(),
+                    role: Some(AuthorRole::Author)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_every_result() {
+        let client_processor = Processor::new();
+        let html_content = fs::read_to_string("benches/benchmark_page.htm").unwrap();
+        let document = Html::parse_document(&html_content);
+
+        let books = client_processor
+            .search_results_in_document(&document)
+            .unwrap();
+        assert_eq!(books.len(), 25);
+        assert_eq!(books[0].libgen_id, Some(LibgenId(12091)));
+    }
+
+    #[test]
+    fn parse_result_typed_identifiers() {
+        let client_processor = Processor::new();
+        let html_content = fs::read_to_string("benches/benchmark_page.htm").unwrap();
+        let document = Html::parse_document(&html_content);
+
+        let book = client_processor
+            .search_title_in_document(&document, "Performance Evaluation and Benchmarking")
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(book.libgen_id, Some(LibgenId(25803)));
+        assert_eq!(book.libgen_md5.as_str(), "d668ff05d1ceae78cff1825faac398ea");
+        let isbns: Vec<&str> = book.isbns.iter().map(|isbn| isbn.as_str()).collect();
+        assert_eq!(isbns, vec!["0849336228", "9780849336225", "9781420037425"]);
+    }
+
+    #[test]
+    fn parse_download_page_cids() {
+        let client_processor = Processor::new();
+        let document = Html::parse_document(
+            r#"<div id="download"><h2><a href="https://download.library.lol/main/3000/5fa82be26689a4e6f4415ea068d35a9d/cats.pdf">GET</a></h2>
+            <ul><li><a href="https://cloudflare-ipfs.com/ipfs/bafykbzacedtnd2yrnb4ue4dqu3ri7fxclj3ttiq3bibdyhb6pm5fv7zpefbxy?filename=cats.pdf">Cloudflare</a></li>
+            <li><a href="https://ipfs.io/ipfs/bafykbzacedtnd2yrnb4ue4dqu3ri7fxclj3ttiq3bibdyhb6pm5fv7zpefbxy?filename=cats.pdf">IPFS.io</a></li></ul></div>"#,
+        );
+
+        assert_eq!(
+            client_processor.parse_download_url(&document).as_deref(),
+            Some(
+                "https://download.library.lol/main/3000/5fa82be26689a4e6f4415ea068d35a9d/cats.pdf"
+            )
+        );
+        let cids = client_processor.parse_ipfs_cids(&document);
+        assert_eq!(cids.len(), 1);
+        assert_eq!(
+            cids[0].as_str(),
+            "bafykbzacedtnd2yrnb4ue4dqu3ri7fxclj3ttiq3bibdyhb6pm5fv7zpefbxy"
+        );
+    }
+
+    #[test]
+    fn parse_detail_page_hashes() {
+        let client_processor = Processor::new();
+        let document = Html::parse_document(
+            r#"<table><tr><td><font color="gray">Size:</font></td><td>3 Mb (3 076 236 bytes)</td></tr>
+            <tr><td colspan=2><table class="hashes">
+            <tr><th>MD5</th><td>5FA82BE26689A4E6F4415EA068D35A9D</td></tr>
+            <tr><th>SHA1</th><td>A94A8FE5CCB19BA61C4C0873D391E987982FBBD3</td></tr>
+            <tr><th>TTH</th><td>QWERTYUIOPASDFGHJKLZXCVBNM234567QWERTYU</td></tr>
+            <tr><th>BTIH</th><td>2ddc1a0b3ab6bb8ebe2f9a7f5b3e5b4e4e4c1b6f</td></tr>
+            </table></td></tr></table>"#,
+        );
+
+        let details = client_processor.parse_detail_page(&document);
+        assert_eq!(details.size_bytes, Some(3076236));
+        assert_eq!(
+            details.btih.as_deref(),
+            Some("2ddc1a0b3ab6bb8ebe2f9a7f5b3e5b4e4e4c1b6f")
+        );
+        assert_eq!(
+            details.tth.as_deref(),
+            Some("QWERTYUIOPASDFGHJKLZXCVBNM234567QWERTYU")
+        );
+        assert!(details.sha1.is_some());
+        assert_eq!(details.sha256, None);
+    }
+
+    #[test]
+    fn detect_cloudflare_challenge() {
+        let client_processor = Processor::new();
+        let document = Html::parse_document(
+            r#"<html><head><title>Just a moment...</title></head>
+            <body><div id="challenge-running">Checking your browser</div></body></html>"#,
+        );
+        assert_eq!(
+            client_processor.search_title_in_document(&document, "cats"),
+            Err(LibgenError::ChallengeError)
+        );
+    }
+
+    #[test]
+    fn detect_rate_limit_page() {
+        let client_processor = Processor::new();
+        let document = Html::parse_document(
+            "<html><head><title>Error</title></head><body><h1>Too many requests</h1></body></html>",
+        );
+        assert_eq!(
+            client_processor.detect_interstitial(&document),
+            Some(LibgenError::RateLimitedError)
+        );
+    }
+
+    #[test]
+    fn long_pages_mentioning_rate_limits_are_real() {
+        let client_processor = Processor::new();
+        let document = Html::parse_document(&format!(
+            "<html><head><title>Rate limiting in practice</title></head><body><h1>Rate \
+             limiting in practice</h1><p>{}</p></body></html>",
+            "How servers answer too many requests. ".repeat(80)
+        ));
+        assert_eq!(client_processor.detect_interstitial(&document), None);
+    }
+
+    #[test]
+    fn detail_pages_are_recognised_by_their_hashes() {
+        let client_processor = Processor::new();
+        let document = Html::parse_document(
+            "<html><head><title>Too many requests</title></head><body><h1>Too many requests</h1>\
+             <table><tr><td>MD5:</td><td>5EB63BBBE01EEED093CB22BB8F5ACDC3</td></tr></table>\
+             </body></html>",
+        );
+        assert_eq!(client_processor.detect_detail_interstitial(&document), None);
+        let notice = Html::parse_document(
+            "<html><head><title>Error</title></head><body><h1>Too many requests</h1></body></html>",
+        );
+        assert_eq!(
+            client_processor.detect_detail_interstitial(&notice),
+            Some(LibgenError::RateLimitedError)
+        );
+    }
+
+    #[test]
+    fn results_page_is_not_interstitial() {
+        let client_processor = Processor::new();
+        let html_content = fs::read_to_string("benches/benchmark_page.htm").unwrap();
+        let document = Html::parse_document(&html_content);
+        assert_eq!(client_processor.detect_interstitial(&document), None);
+        // An empty but genuine page is still a plain miss
+        let empty = Html::parse_document(
+            "<table class=c><tr><td>ID</td><td>Author(s)</td><td>Title</td><td>Publisher</td>\
+             <td>Year</td><td>Extension</td></tr></table>",
+        );
+        assert_eq!(
+            client_processor.search_title_in_document(&empty, "cats"),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn columns_mapped_by_header_text() {
+        let client_processor = Processor::new();
+        // Year and Publisher swapped, an extra column in front
+        let document = Html::parse_document(
+            r#"<table class=c><tr><td><b>#</b></td><td><b>ID</b></td><td><b>Author(s)</b></td>
+            <td><b>Title</b></td><td><b>Year</b></td><td><b>Publisher</b></td>
+            <td colspan=2><b>Mirrors</b></td><td><b>Extension</b></td></tr>
+            <tr><td>1</td><td>3750</td><td><a href="search.php?req=Tom">Tom</a></td>
+            <td width=500><a href="book/index.php?md5=5FA82BE26689A4E6F4415EA068D35A9D" id=3750>Cats</a></td>
+            <td>1990</td><td>Wiley</td><td></td><td></td><td>pdf</td></tr></table>"#,
+        );
+
+        let book = client_processor
+            .search_title_in_document(&document, "Cats")
+            .unwrap()
+            .unwrap();
+        assert_eq!(book.libgen_id, Some(LibgenId(3750)));
+        assert_eq!(book.year, Some(1990));
+        assert_eq!(book.publisher, "Wiley");
+        assert_eq!(book.file_type, "pdf");
+        assert_eq!(book.authors, vec![Author::new("Tom")]);
+    }
+
+    #[test]
+    fn huge_colspans_are_clamped() {
+        let client_processor = Processor::new();
+        let document = Html::parse_document(
+            "<table class=c><tr><td>ID</td><td>Author(s)</td><td>Title</td><td>Publisher</td>\
+             <td>Year</td><td colspan=18446744073709551615>Mirrors</td><td>Extension</td></tr>\
+             </table>",
+        );
+        let table = document
+            .select(&Selector::parse("table").unwrap())
+            .next()
+            .unwrap();
+        let columns = client_processor.parse_results_header(table).unwrap();
+        assert_eq!(columns.extension, 5 + MAX_COLSPAN);
+    }
+
+    #[test]
+    fn missing_columns_are_layout_changes() {
+        let client_processor = Processor::new();
+        let document = Html::parse_document(
+            "<table class=c><tr><td>ID</td><td>Author(s)</td><td>Name</td><td>Publisher</td>\
+             <td>Year</td><td>Format</td></tr></table>",
+        );
+        assert_eq!(
+            client_processor.search_title_in_document(&document, "cats"),
+            Err(LibgenError::LayoutChanged(
+                "missing columns Title, Extension in results header \
+                 [ID, Author(s), Name, Publisher, Year, Format]"
+                    .to_owned()
+            ))
+        );
+
+        let no_table = Html::parse_document("<p>Nothing here</p>");
+        assert!(matches!(
+            client_processor.search_title_in_document(&no_table, "cats"),
+            Err(LibgenError::LayoutChanged(_))
+        ));
+    }
+
     #[test]
     fn parse_result_partial_existing_title() {
         // Existing, as in its located in the downloaded html file /benches
@@ -0,0 +1,219 @@
+use core::fmt;
+use reqwest::{ClientBuilder, NoProxy, Proxy};
+use std::env;
+
+/// The default SOCKS port of a local Tor daemon, `socks5h` so Tor also resolves names
+pub const TOR_PROXY: &str = "socks5h://127.0.0.1:9050";
+
+const SUPPORTED_SCHEMES: [&str; 4] = ["http", "https", "socks5", "socks5h"];
+
+/// Errors in a proxy configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProxyError {
+    /// The proxy url could not be parsed or uses an unsupported scheme.
+    InvalidProxyUrl(String),
+    /// The http client could not be built with this configuration.
+    ClientError(String),
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyError::InvalidProxyUrl(url) => write!(f, "InvalidProxyUrl: {}", url),
+            ProxyError::ClientError(err) => write!(f, "ClientError: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ProxyError {}
+
+/// Proxies used for both searching and downloading.
+///
+/// Urls may use the `http`, `https`, `socks5` or `socks5h` schemes. With `socks5h`
+/// host names are resolved by the proxy, which is what Tor needs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProxyConfig {
+    /// Proxy for every request
+    pub all: Option<String>,
+    /// Proxy for `http://` requests, takes precedence over `all`
+    pub http: Option<String>,
+    /// Proxy for `https://` requests, takes precedence over `all`
+    pub https: Option<String>,
+    /// Comma separated hosts that bypass the proxy, as in `NO_PROXY`
+    pub no_proxy: Option<String>,
+}
+
+impl ProxyConfig {
+    /// Sends every request through one proxy
+    pub fn all(url: &str) -> ProxyConfig {
+        ProxyConfig {
+            all: Some(url.to_owned()),
+            ..ProxyConfig::default()
+        }
+    }
+
+    /// Sends every request through a local Tor daemon, see [`TOR_PROXY`]
+    pub fn tor() -> ProxyConfig {
+        ProxyConfig::all(TOR_PROXY)
+    }
+
+    /// Reads `ALL_PROXY`, `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY`, upper or lower case
+    pub fn from_env() -> ProxyConfig {
+        fn var(name: &str) -> Option<String> {
+            env::var(name)
+                .or_else(|_| env::var(name.to_ascii_lowercase()))
+                .ok()
+                .filter(|value| !value.trim().is_empty())
+        }
+        ProxyConfig {
+            all: var("ALL_PROXY"),
+            http: var("HTTP_PROXY"),
+            https: var("HTTPS_PROXY"),
+            no_proxy: var("NO_PROXY"),
+        }
+    }
+
+    /// Whether any proxy is set
+    pub fn is_empty(&self) -> bool {
+        self.all.is_none() && self.http.is_none() && self.https.is_none()
+    }
+
+    /// Configures a client builder to use these proxies and nothing else
+    pub fn apply(&self, builder: ClientBuilder) -> Result<ClientBuilder, ProxyError> {
+        // Don't let reqwest mix in proxies from the environment on its own
+        let mut builder = builder.no_proxy();
+        let no_proxy = self.no_proxy.as_deref().and_then(NoProxy::from_string);
+
+        // Scheme specific proxies are added first so reqwest prefers them
+        let proxies = [
+            (self.http.as_deref(), "http"),
+            (self.https.as_deref(), "https"),
+            (self.all.as_deref(), "all"),
+        ];
+        for (url, target) in proxies {
+            let Some(url) = url else {
+                continue;
+            };
+            validate_proxy_url(url)?;
+            let proxy = match target {
+                "http" => Proxy::http(url),
+                "https" => Proxy::https(url),
+                _ => Proxy::all(url),
+            }
+            .map_err(|_| ProxyError::InvalidProxyUrl(url.to_owned()))?
+            .no_proxy(no_proxy.clone());
+            builder = builder.proxy(proxy);
+        }
+        Ok(builder)
+    }
+}
+
+fn validate_proxy_url(url: &str) -> Result<(), ProxyError> {
+    let parsed = url::Url::parse(url).map_err(|_| ProxyError::InvalidProxyUrl(url.to_owned()))?;
+    if SUPPORTED_SCHEMES.contains(&parsed.scheme()) && parsed.host().is_some() {
+        Ok(())
+    } else {
+        Err(ProxyError::InvalidProxyUrl(url.to_owned()))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// A SOCKS5 server that answers every CONNECT itself with `body`
+    ///
+    /// Returns its url and the host names clients asked it to connect to.
+    pub(crate) async fn socks_stand_in(body: &'static [u8]) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let targets = Arc::new(Mutex::new(Vec::new()));
+        let seen = targets.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let seen = seen.clone();
+                tokio::spawn(async move {
+                    // Greeting, only "no authentication" is offered back
+                    let mut header = [0u8; 2];
+                    stream.read_exact(&mut header).await.unwrap();
+                    let mut methods = vec![0u8; header[1] as usize];
+                    stream.read_exact(&mut methods).await.unwrap();
+                    stream.write_all(&[5, 0]).await.unwrap();
+
+                    // CONNECT request
+                    let mut request = [0u8; 4];
+                    stream.read_exact(&mut request).await.unwrap();
+                    let target = match request[3] {
+                        3 => {
+                            let mut length = [0u8; 1];
+                            stream.read_exact(&mut length).await.unwrap();
+                            let mut domain = vec![0u8; length[0] as usize];
+                            stream.read_exact(&mut domain).await.unwrap();
+                            String::from_utf8(domain).unwrap()
+                        }
+                        _ => {
+                            let mut ip = [0u8; 4];
+                            stream.read_exact(&mut ip).await.unwrap();
+                            std::net::Ipv4Addr::from(ip).to_string()
+                        }
+                    };
+                    let mut port = [0u8; 2];
+                    stream.read_exact(&mut port).await.unwrap();
+                    seen.lock().unwrap().push(target);
+                    stream
+                        .write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0])
+                        .await
+                        .unwrap();
+
+                    let mut http_request = [0u8; 4096];
+                    let _ = stream.read(&mut http_request).await;
+                    let header = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    );
+                    let _ = stream.write_all(header.as_bytes()).await;
+                    let _ = stream.write_all(body).await;
+                });
+            }
+        });
+        (format!("socks5h://{}", address), targets)
+    }
+
+    #[test]
+    fn rejects_unsupported_scheme() {
+        let config = ProxyConfig::all("ftp://127.0.0.1:21");
+        assert_eq!(
+            config.apply(reqwest::Client::builder()).err(),
+            Some(ProxyError::InvalidProxyUrl("ftp://127.0.0.1:21".to_owned()))
+        );
+        assert!(ProxyConfig::tor().apply(reqwest::Client::builder()).is_ok());
+    }
+
+    #[tokio::test]
+    async fn socks5h_resolves_remotely() {
+        let (proxy, targets) = socks_stand_in(b"through the proxy").await;
+        let client = ProxyConfig::all(&proxy)
+            .apply(reqwest::Client::builder())
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let body = client
+            .get("http://libgen.invalid/search.php")
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        assert_eq!(body, "through the proxy");
+        // The name reached the proxy unresolved
+        assert_eq!(*targets.lock().unwrap(), vec!["libgen.invalid".to_string()]);
+    }
+}
@@ -0,0 +1,504 @@
+use core::fmt;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    fs,
+    sync::{Mutex, Semaphore},
+    task::JoinSet,
+};
+
+use crate::{
+    book::LibgenBook,
+    downloader::{DownloadError, DownloadedFile, Downloader},
+    identifiers::Md5,
+};
+
+/// Errors from loading, saving or running a queue
+#[derive(Debug)]
+pub enum QueueError {
+    /// The state file could not be read or written.
+    IOError(String),
+    /// The state file is not valid queue state.
+    ParsingError(String),
+}
+
+impl fmt::Display for QueueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueueError::IOError(err) => write!(f, "IOError: {}", err),
+            QueueError::ParsingError(err) => write!(f, "ParsingError: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for QueueError {}
+
+impl From<std::io::Error> for QueueError {
+    fn from(error: std::io::Error) -> Self {
+        QueueError::IOError(error.to_string())
+    }
+}
+
+impl From<serde_json::Error> for QueueError {
+    fn from(error: serde_json::Error) -> Self {
+        QueueError::ParsingError(error.to_string())
+    }
+}
+
+/// Where a queued book is in its download
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DownloadStatus {
+    /// Waiting for a free download slot.
+    Pending,
+    /// Currently downloading.
+    Active,
+    /// The last attempt failed, it will be tried again after a backoff.
+    Retrying {
+        /// Attempts made so far
+        attempts: u32,
+        /// Why the last attempt failed
+        error: String,
+    },
+    /// Downloaded and verified.
+    Completed(DownloadedFile),
+    /// Gave up after running out of retries.
+    Failed {
+        /// Attempts made
+        attempts: u32,
+        /// Why the last attempt failed
+        error: String,
+    },
+}
+
+impl DownloadStatus {
+    /// Whether the queue is done with this item
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            DownloadStatus::Completed(_) | DownloadStatus::Failed { .. }
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[doc = r" A book in the queue and its progress."]
+pub struct QueueItem {
+    /// The book to download
+    pub book: LibgenBook,
+    /// Its current status
+    pub status: DownloadStatus,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[doc = r" Limits and retry behaviour for a [`DownloadQueue`]."]
+pub struct QueueConfig {
+    /// Downloads running at once across all mirrors
+    pub concurrency: usize,
+    /// Downloads running at once against a single mirror
+    pub per_host: usize,
+    /// Attempts before an item is marked as failed
+    pub max_attempts: u32,
+    /// Wait after the first failure, doubled for every following one
+    pub backoff: Duration,
+    /// File the queue is persisted to after every change
+    pub state_path: Option<PathBuf>,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        QueueConfig {
+            concurrency: 4,
+            per_host: 2,
+            max_attempts: 3,
+            backoff: Duration::from_secs(30),
+            state_path: None,
+        }
+    }
+}
+
+struct QueueInner {
+    downloader: Downloader,
+    config: QueueConfig,
+    items: Mutex<Vec<QueueItem>>,
+    /// Items a [`DownloadQueue::run`] is working on, so concurrent runs skip them
+    claimed: Mutex<HashSet<Md5>>,
+    slots: Semaphore,
+    host_slots: HashMap<String, Semaphore>,
+}
+
+/// Downloads many books with bounded concurrency, retries and resumable state.
+///
+/// Cloning the queue gives another handle to the same items, so status can be
+/// read from one task while another is running the queue.
+#[derive(Clone)]
+pub struct DownloadQueue {
+    inner: Arc<QueueInner>,
+}
+
+impl DownloadQueue {
+    /// Creates an empty queue
+    pub fn new(downloader: Downloader, config: QueueConfig) -> DownloadQueue {
+        DownloadQueue::with_items(downloader, config, Vec::new())
+    }
+
+    /// Creates a queue from `config.state_path`, or an empty one if the file doesn't exist
+    ///
+    /// Items that were active when the state was saved are downloaded again.
+    pub async fn load(
+        downloader: Downloader,
+        config: QueueConfig,
+    ) -> Result<DownloadQueue, QueueError> {
+        let mut items: Vec<QueueItem> = match &config.state_path {
+            Some(path) if fs::try_exists(path).await? => {
+                serde_json::from_slice(&fs::read(path).await?)?
+            }
+            _ => Vec::new(),
+        };
+        for item in items.iter_mut() {
+            if item.status == DownloadStatus::Active {
+                item.status = DownloadStatus::Pending;
+            }
+        }
+        Ok(DownloadQueue::with_items(downloader, config, items))
+    }
+
+    fn with_items(
+        downloader: Downloader,
+        config: QueueConfig,
+        items: Vec<QueueItem>,
+    ) -> DownloadQueue {
+        let host_slots = downloader
+            .hosts()
+            .iter()
+            .map(|host| (host.clone(), Semaphore::new(config.per_host.max(1))))
+            .collect();
+        DownloadQueue {
+            inner: Arc::new(QueueInner {
+                slots: Semaphore::new(config.concurrency.max(1)),
+                host_slots,
+                downloader,
+                config,
+                items: Mutex::new(items),
+                claimed: Mutex::new(HashSet::new()),
+            }),
+        }
+    }
+
+    /// The downloader used by this queue
+    pub fn downloader(&self) -> &Downloader {
+        &self.inner.downloader
+    }
+
+    /// Caps the combined speed of the queue in bytes per second, takes effect immediately
+    pub fn set_bandwidth_limit(&self, bytes_per_second: Option<u64>) {
+        self.inner.downloader.set_bandwidth_limit(bytes_per_second);
+    }
+
+    /// Caps the speed of each download in the queue, takes effect immediately
+    pub fn set_per_download_limit(&self, bytes_per_second: Option<u64>) {
+        self.inner
+            .downloader
+            .set_per_download_limit(bytes_per_second);
+    }
+
+    /// Adds a book, books already in the queue are ignored
+    ///
+    /// Returns whether the book was added.
+    pub async fn push(&self, book: LibgenBook) -> Result<bool, QueueError> {
+        {
+            let mut items = self.inner.items.lock().await;
+            if items
+                .iter()
+                .any(|item| item.book.libgen_md5 == book.libgen_md5)
+            {
+                return Ok(false);
+            }
+            items.push(QueueItem {
+                book,
+                status: DownloadStatus::Pending,
+            });
+        }
+        self.save().await?;
+        Ok(true)
+    }
+
+    /// A snapshot of every item and its status
+    pub async fn items(&self) -> Vec<QueueItem> {
+        self.inner.items.lock().await.clone()
+    }
+
+    /// The status of a single book
+    pub async fn status(&self, md5: &Md5) -> Option<DownloadStatus> {
+        self.inner
+            .items
+            .lock()
+            .await
+            .iter()
+            .find(|item| &item.book.libgen_md5 == md5)
+            .map(|item| item.status.clone())
+    }
+
+    /// Downloads every unfinished item, returns once all are completed or failed
+    ///
+    /// Items another run is already working on are left to that run.
+    pub async fn run(&self) -> Result<(), QueueError> {
+        let unfinished: Vec<(LibgenBook, u32)> = {
+            let items = self.inner.items.lock().await;
+            let mut claimed = self.inner.claimed.lock().await;
+            items
+                .iter()
+                .filter(|item| !item.status.is_finished())
+                .filter(|item| claimed.insert(item.book.libgen_md5.clone()))
+                .map(|item| {
+                    let attempts = match item.status {
+                        DownloadStatus::Retrying { attempts, .. } => attempts,
+                        _ => 0,
+                    };
+                    (item.book.clone(), attempts)
+                })
+                .collect()
+        };
+
+        let mut tasks = JoinSet::new();
+        for (book, attempts) in unfinished {
+            let queue = self.clone();
+            tasks.spawn(async move {
+                let result = queue.run_item(&book, attempts).await;
+                queue.inner.claimed.lock().await.remove(&book.libgen_md5);
+                result
+            });
+        }
+        while let Some(result) = tasks.join_next().await {
+            result.map_err(|err| QueueError::IOError(err.to_string()))??;
+        }
+        Ok(())
+    }
+
+    async fn run_item(&self, book: &LibgenBook, mut attempts: u32) -> Result<(), QueueError> {
+        let config = &self.inner.config;
+        loop {
+            if attempts > 0 {
+                let backoff = config.backoff * 2u32.saturating_pow(attempts - 1);
+                tokio::time::sleep(backoff).await;
+            }
+
+            let result = {
+                let _slot = self.inner.slots.acquire().await.unwrap();
+                self.set_status(&book.libgen_md5, DownloadStatus::Active)
+                    .await?;
+                self.download_any_host(book).await
+            };
+            attempts += 1;
+
+            let status = match result {
+                Ok(file) => DownloadStatus::Completed(file),
+                Err(err) if attempts >= config.max_attempts => DownloadStatus::Failed {
+                    attempts,
+                    error: err.to_string(),
+                },
+                Err(err) => DownloadStatus::Retrying {
+                    attempts,
+                    error: err.to_string(),
+                },
+            };
+            let finished = status.is_finished();
+            self.set_status(&book.libgen_md5, status).await?;
+            if finished {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Tries each mirror in turn, waiting for a free slot on each
+    ///
+    /// Books without a direct link come from the one link on their download page.
+    async fn download_any_host(&self, book: &LibgenBook) -> Result<DownloadedFile, DownloadError> {
+        let downloader = &self.inner.downloader;
+        if !Downloader::has_direct_link(book) {
+            return downloader.download(book).await;
+        }
+        let mut last_error = DownloadError::ConnectionError("No download hosts".to_string());
+        for host in downloader.hosts() {
+            let _host_slot = match self.inner.host_slots.get(host) {
+                Some(slots) => Some(slots.acquire().await.unwrap()),
+                None => None,
+            };
+            match downloader.download_from_host(book, host).await {
+                Ok(file) => return Ok(file),
+                Err(err) => last_error = err,
+            }
+        }
+        Err(last_error)
+    }
+
+    async fn set_status(&self, md5: &Md5, status: DownloadStatus) -> Result<(), QueueError> {
+        {
+            let mut items = self.inner.items.lock().await;
+            if let Some(item) = items.iter_mut().find(|item| &item.book.libgen_md5 == md5) {
+                item.status = status;
+            }
+        }
+        self.save().await
+    }
+
+    /// Writes the queue to `state_path`, through a temporary file so a crash can't truncate it
+    async fn save(&self) -> Result<(), QueueError> {
+        let Some(path) = &self.inner.config.state_path else {
+            return Ok(());
+        };
+        // Holding the lock while writing keeps saves in order
+        let items = self.inner.items.lock().await;
+        Ok(write_atomically(path, &serde_json::to_vec_pretty(&*items)?).await?)
+    }
+}
+
+/// Replaces `path` through a temporary file, so a crash can't leave it truncated
+pub(crate) async fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    fs::write(&temporary, contents).await?;
+    fs::rename(&temporary, path).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        book::Section,
+        downloader::tests::{book_with_md5, serve_bytes},
+        parser::Mirror,
+        processor::Processor,
+    };
+
+    // md5 of "hello world"
+    const HELLO_MD5: &str = "5eb63bbbe01eeed093cb22bb8f5acdc3";
+
+    fn test_config(state_path: Option<PathBuf>) -> QueueConfig {
+        QueueConfig {
+            concurrency: 2,
+            per_host: 1,
+            max_attempts: 2,
+            backoff: Duration::from_millis(1),
+            state_path,
+        }
+    }
+
+    #[tokio::test]
+    async fn queue_reads_fiction_links_from_the_download_page() {
+        let file_host = serve_bytes(b"hello world").await;
+        let page: &'static str = Box::leak(
+            format!(
+                r#"<div id="download"><h2><a href="{}/fiction/book.epub">GET</a></h2></div>"#,
+                file_host
+            )
+            .into_boxed_str(),
+        );
+        let page_host = serve_bytes(page.as_bytes()).await;
+        let directory = tempfile::tempdir().unwrap();
+        let mut downloader = Downloader::new(Some(directory.path().display().to_string()));
+        downloader.set_hosts(vec!["http://files.libgen.invalid".to_string()]);
+        downloader.set_download_page(Mirror::new(&page_host, Processor::new()));
+
+        let queue = DownloadQueue::new(downloader, test_config(None));
+        let novel = LibgenBook {
+            libgen_id: None,
+            section: Section::Fiction,
+            ..book_with_md5(HELLO_MD5)
+        };
+        queue.push(novel.clone()).await.unwrap();
+        queue.run().await.unwrap();
+
+        assert!(matches!(
+            queue.status(&novel.libgen_md5).await,
+            Some(DownloadStatus::Completed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn queue_completes_and_fails_items() {
+        let host = serve_bytes(b"hello world").await;
+        let directory = tempfile::tempdir().unwrap();
+        let mut downloader = Downloader::new(Some(directory.path().display().to_string()));
+        downloader.set_hosts(vec![host]);
+
+        let queue = DownloadQueue::new(downloader, test_config(None));
+        let good = book_with_md5(HELLO_MD5);
+        let mut bad = book_with_md5("00000000000000000000000000000000");
+        bad.title = "Another book".to_owned();
+        assert!(queue.push(good.clone()).await.unwrap());
+        assert!(!queue.push(good.clone()).await.unwrap());
+        queue.push(bad.clone()).await.unwrap();
+
+        queue.run().await.unwrap();
+
+        assert!(matches!(
+            queue.status(&good.libgen_md5).await,
+            Some(DownloadStatus::Completed(_))
+        ));
+        assert!(matches!(
+            queue.status(&bad.libgen_md5).await,
+            Some(DownloadStatus::Failed { attempts: 2, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn concurrent_runs_download_once() {
+        let host = serve_bytes(b"hello world").await;
+        let directory = tempfile::tempdir().unwrap();
+        let mut downloader = Downloader::new(Some(directory.path().display().to_string()));
+        downloader.set_hosts(vec![host]);
+
+        let queue = DownloadQueue::new(downloader, test_config(None));
+        let book = book_with_md5(HELLO_MD5);
+        queue.push(book.clone()).await.unwrap();
+        let (first, second) = tokio::join!(queue.run(), queue.run());
+        first.unwrap();
+        second.unwrap();
+
+        assert!(matches!(
+            queue.status(&book.libgen_md5).await,
+            Some(DownloadStatus::Completed(_))
+        ));
+        assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn queue_resumes_from_state_file() {
+        let host = serve_bytes(b"hello world").await;
+        let directory = tempfile::tempdir().unwrap();
+        let state_path = directory.path().join("queue.json");
+        let mut downloader = Downloader::new(Some(directory.path().display().to_string()));
+        downloader.set_hosts(vec![host]);
+
+        let book = book_with_md5(HELLO_MD5);
+        {
+            let queue =
+                DownloadQueue::new(downloader.clone(), test_config(Some(state_path.clone())));
+            queue.push(book.clone()).await.unwrap();
+            // Pretend we were interrupted mid download
+            queue
+                .set_status(&book.libgen_md5, DownloadStatus::Active)
+                .await
+                .unwrap();
+        }
+
+        let queue = DownloadQueue::load(downloader, test_config(Some(state_path)))
+            .await
+            .unwrap();
+        assert_eq!(
+            queue.status(&book.libgen_md5).await,
+            Some(DownloadStatus::Pending)
+        );
+        queue.run().await.unwrap();
+        assert!(matches!(
+            queue.status(&book.libgen_md5).await,
+            Some(DownloadStatus::Completed(_))
+        ));
+    }
+}
@@ -0,0 +1,435 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
+use urlencoding::encode;
+
+use crate::{
+    book::{Author, LibgenBook, Section},
+    identifiers::{Doi, Md5},
+    processor::{row_cells, Processor},
+    scraper::LibgenError,
+    util::{parse_authors, parse_md5_from_url},
+};
+
+lazy_static! {
+    static ref DOI: Regex = Regex::new(r"(?i)DOI:\s*(\S+)").unwrap();
+    static ref VOLUME: Regex = Regex::new(r"(?i)volume\s+([^\s,(]+)").unwrap();
+    static ref ISSUE: Regex = Regex::new(r"(?i)issue\s+([^\s,(]+)").unwrap();
+    static ref YEAR: Regex = Regex::new(r"\((\d{4})\)").unwrap();
+    static ref PAGES: Regex = Regex::new(r"(?i)\bpp?\.\s*([\w-]+)").unwrap();
+}
+
+/// Header labels of the article result columns we read
+const AUTHORS_COLUMN: &str = "Author(s)";
+const ARTICLE_COLUMN: &str = "Article";
+const JOURNAL_COLUMN: &str = "Journal";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[doc = r" A scientific article from the scimag collection."]
+pub struct LibgenArticle {
+    /// The articles DOI
+    pub doi: Doi,
+    /// Article title
+    pub title: String,
+    /// Authors of the article
+    pub authors: Vec<Author>,
+    /// The journal it was published in
+    pub journal: Option<String>,
+    /// Journal volume
+    pub volume: Option<String>,
+    /// Journal issue
+    pub issue: Option<String>,
+    /// Year of publication
+    pub year: Option<u16>,
+    /// Page range, e.g. `45-67`
+    pub pages: Option<String>,
+    /// MD5 of the file, search results don't always list it
+    pub md5: Option<Md5>,
+}
+
+/// The path of an article, e.g. `scimag/10.1038/nature12373`, its DOI encoded segment by segment
+pub(crate) fn article_path(doi: &Doi) -> String {
+    let segments: Vec<_> = doi
+        .as_str()
+        .split('/')
+        .map(|segment| encode(segment).into_owned())
+        .collect();
+    format!("scimag/{}", segments.join("/"))
+}
+
+impl LibgenArticle {
+    /// The articles path on a download mirror, e.g. `/scimag/10.1038/nature12373`
+    pub fn download_path(&self) -> String {
+        format!("/{}", article_path(&self.doi))
+    }
+
+    /// The article as a book, used to name and check its file
+    ///
+    /// The journal stands in for the publisher. Articles have no libgen id, so
+    /// neither an `{id}` in file names nor a torrent name is filled in. Returns
+    /// `None` while the MD5 is unknown.
+    pub fn to_book(&self) -> Option<LibgenBook> {
+        Some(LibgenBook {
+            libgen_id: None,
+            title: self.title.clone(),
+            authors: self.authors.clone(),
+            publisher: self.journal.clone().unwrap_or_default(),
+            libgen_md5: self.md5.clone()?,
+            file_type: "pdf".to_owned(),
+            isbns: vec![],
+            year: self.year,
+            series: None,
+            section: Section::Scimag,
+        })
+    }
+}
+
+/// A search of the scimag collection
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScimagQuery {
+    /// Words from the article title
+    pub title: Option<String>,
+    /// The journal name, results from other journals are dropped
+    pub journal: Option<String>,
+}
+
+impl ScimagQuery {
+    /// Searches articles by title
+    pub fn title(title: &str) -> ScimagQuery {
+        ScimagQuery {
+            title: Some(title.to_owned()),
+            journal: None,
+        }
+    }
+
+    /// Searches articles published in a journal
+    pub fn journal(journal: &str) -> ScimagQuery {
+        ScimagQuery {
+            title: None,
+            journal: Some(journal.to_owned()),
+        }
+    }
+
+    /// Also limits the results to a journal
+    pub fn in_journal(mut self, journal: &str) -> Self {
+        self.journal = Some(journal.to_owned());
+        self
+    }
+
+    /// Path and query of the search, relative to the mirror
+    pub fn path(&self) -> String {
+        let terms: Vec<&str> = [self.title.as_deref(), self.journal.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect();
+        format!("scimag/?q={}", encode(&terms.join(" ")))
+    }
+
+    /// Whether an article passes the journal filter
+    pub fn matches(&self, article: &LibgenArticle) -> bool {
+        let Some(journal) = &self.journal else {
+            return true;
+        };
+        article
+            .journal
+            .as_ref()
+            .is_some_and(|name| name.to_lowercase().contains(&journal.trim().to_lowercase()))
+    }
+}
+
+/// Positions of the article result columns, found from the table header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ArticleColumns {
+    authors: usize,
+    article: usize,
+    journal: usize,
+}
+
+/// Parses the scimag results and article pages
+pub struct ScimagParser {
+    /// CSS selector
+    pub results_table_selector: Selector,
+    /// CSS selector
+    pub row_selector: Selector,
+    /// CSS selector
+    pub link_selector: Selector,
+    processor: Processor,
+}
+
+impl Default for ScimagParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScimagParser {
+    /// Creates a new scimag parser with the needed css
+    pub fn new() -> Self {
+        Self {
+            results_table_selector: Selector::parse("table.catalog").unwrap(),
+            row_selector: Selector::parse("tr").unwrap(),
+            link_selector: Selector::parse("a[href]").unwrap(),
+            processor: Processor::new(),
+        }
+    }
+
+    /// Recognises challenge and rate limit pages served instead of a scimag page
+    pub fn detect_interstitial(&self, html_document: &Html) -> Option<LibgenError> {
+        // The catalog table marks a real results page, the DOI row an article page
+        if html_document
+            .select(&self.results_table_selector)
+            .next()
+            .is_some()
+            || self.parse_article_page(html_document).is_some()
+        {
+            return None;
+        }
+        self.processor.detect_blocked_page(html_document)
+    }
+
+    fn parse_header(&self, header: ElementRef<'_>) -> Result<ArticleColumns, LibgenError> {
+        let labels: Vec<String> = row_cells(header)
+            .iter()
+            .map(|cell| cell.text().collect::<String>().trim().to_owned())
+            .collect();
+        let position = |name: &str| {
+            labels
+                .iter()
+                .position(|label| label.eq_ignore_ascii_case(name))
+        };
+
+        match (
+            position(AUTHORS_COLUMN),
+            position(ARTICLE_COLUMN),
+            position(JOURNAL_COLUMN),
+        ) {
+            (Some(authors), Some(article), Some(journal)) => Ok(ArticleColumns {
+                authors,
+                article,
+                journal,
+            }),
+            _ => Err(LibgenError::LayoutChanged(format!(
+                "scimag results header [{}] lacks {}, {} or {}",
+                labels.join(", "),
+                AUTHORS_COLUMN,
+                ARTICLE_COLUMN,
+                JOURNAL_COLUMN
+            ))),
+        }
+    }
+
+    /// The first MD5 among a rows links, e.g. in its mirrors
+    fn row_md5(&self, row: ElementRef<'_>) -> Option<Md5> {
+        row.select(&self.link_selector)
+            .filter_map(|link| link.value().attr("href"))
+            .find_map(|href| parse_md5_from_url(href).ok())
+    }
+
+    fn parse_row(&self, columns: &ArticleColumns, row: ElementRef<'_>) -> Option<LibgenArticle> {
+        let cells = row_cells(row);
+
+        let article = *cells.get(columns.article)?;
+        let article_text = article.text().collect::<String>();
+        let doi: Doi = DOI.captures(&article_text)?[1].parse().ok()?;
+        let title = article
+            .select(&self.link_selector)
+            .next()
+            .map(|link| link.text().collect::<String>().trim().to_owned())
+            .filter(|title| !title.is_empty())?;
+
+        let authors = parse_authors(&cells.get(columns.authors)?.text().collect::<String>());
+
+        // "Journal of Things" followed by "volume 12 (2010) issue 3, p. 45-67"
+        let journal_cell = *cells.get(columns.journal)?;
+        let journal = journal_cell
+            .select(&self.link_selector)
+            .next()
+            .map(|link| link.text().collect::<String>().trim().to_owned())
+            .filter(|journal| !journal.is_empty());
+        let citation = journal_cell.text().collect::<String>();
+        let capture = |regex: &Regex| {
+            regex
+                .captures(&citation)
+                .map(|captures| captures[1].to_owned())
+        };
+
+        Some(LibgenArticle {
+            doi,
+            title,
+            authors,
+            journal,
+            volume: capture(&VOLUME),
+            issue: capture(&ISSUE),
+            year: capture(&YEAR).and_then(|year| year.parse().ok()),
+            pages: capture(&PAGES),
+            md5: self.row_md5(row),
+        })
+    }
+
+    /// Parses every article on a scimag search page, in page order
+    pub fn search_results_in_document(
+        &self,
+        html_document: &Html,
+    ) -> Result<Vec<LibgenArticle>, LibgenError> {
+        // Searches without results leave out the table altogether
+        let Some(table) = html_document.select(&self.results_table_selector).next() else {
+            return Ok(Vec::new());
+        };
+
+        let mut rows = table.select(&self.row_selector);
+        let columns = match rows.next() {
+            Some(header) => self.parse_header(header)?,
+            None => return Ok(Vec::new()),
+        };
+        Ok(rows
+            .filter_map(|row| self.parse_row(&columns, row))
+            .collect())
+    }
+
+    /// Parses an articles own page, made of label and value rows
+    ///
+    /// Returns `None` when the page has no DOI or title, e.g. for unknown DOIs.
+    pub fn parse_article_page(&self, html_document: &Html) -> Option<LibgenArticle> {
+        let mut fields: Vec<(String, String)> = Vec::new();
+        for row in html_document.select(&self.row_selector) {
+            let cells = row_cells(row);
+            let (Some(label), Some(value)) = (cells.first(), cells.get(1)) else {
+                continue;
+            };
+            let value = value.text().collect::<String>().trim().to_owned();
+            if !value.is_empty() {
+                let label = label.text().collect::<String>();
+                fields.push((
+                    label.trim().trim_end_matches(':').to_ascii_lowercase(),
+                    value,
+                ));
+            }
+        }
+        let field = |names: &[&str]| {
+            fields
+                .iter()
+                .find(|(label, _)| names.contains(&label.as_str()))
+                .map(|(_, value)| value.clone())
+        };
+
+        Some(LibgenArticle {
+            doi: field(&["doi"])?.parse().ok()?,
+            title: field(&["title"])?,
+            authors: field(&["author(s)", "authors"])
+                .map(|authors| parse_authors(&authors))
+                .unwrap_or_default(),
+            journal: field(&["journal"]),
+            volume: field(&["volume"]),
+            issue: field(&["issue"]),
+            year: field(&["year"]).and_then(|year| year.parse().ok()),
+            pages: field(&["pages"]),
+            md5: field(&["md5"]).and_then(|md5| md5.parse().ok()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESULTS_PAGE: &str = r#"<table class="catalog">
+        <thead><tr><th>Author(s)</th><th>Article</th><th>Journal</th><th>Size</th><th>Mirrors</th></tr></thead>
+        <tbody><tr>
+        <td>Smith, J.; Doe, A. (ed.)</td>
+        <td><p><a href="/scimag/10.1038/nature12373">Nanometre-scale thermometry in a living cell</a></p><p>DOI: 10.1038/nature12373</p></td>
+        <td><p><a href="/scimag/journals/1234">Nature</a></p><p>volume 500 (2013) issue 7460, p. 54-58</p></td>
+        <td>1 Mb</td>
+        <td><ul class="record_mirrors"><li><a href="http://library.lol/scimag/5EB63BBBE01EEED093CB22BB8F5ACDC3">[1]</a></li></ul></td>
+        </tr></tbody></table>"#;
+
+    #[test]
+    fn parse_article_results() {
+        let parser = ScimagParser::new();
+        let articles = parser
+            .search_results_in_document(&Html::parse_document(RESULTS_PAGE))
+            .unwrap();
+
+        assert_eq!(articles.len(), 1);
+        let article = &articles[0];
+        assert_eq!(article.doi.as_str(), "10.1038/nature12373");
+        assert_eq!(
+            article.title,
+            "Nanometre-scale thermometry in a living cell"
+        );
+        assert_eq!(article.journal.as_deref(), Some("Nature"));
+        assert_eq!(article.volume.as_deref(), Some("500"));
+        assert_eq!(article.issue.as_deref(), Some("7460"));
+        assert_eq!(article.year, Some(2013));
+        assert_eq!(article.pages.as_deref(), Some("54-58"));
+        assert_eq!(article.authors.len(), 2);
+        assert_eq!(
+            article.md5.as_ref().map(Md5::as_str),
+            Some("5eb63bbbe01eeed093cb22bb8f5acdc3")
+        );
+        assert_eq!(article.download_path(), "/scimag/10.1038/nature12373");
+
+        let book = article.to_book().unwrap();
+        assert_eq!(book.libgen_id, None);
+        assert_eq!(book.section, Section::Scimag);
+        assert_eq!(crate::torrent::torrent_name(&book), None);
+
+        assert!(ScimagQuery::title("thermometry")
+            .in_journal("nature")
+            .matches(article));
+        assert!(!ScimagQuery::journal("Science").matches(article));
+    }
+
+    #[test]
+    fn articles_about_rate_limits_are_real_pages() {
+        let parser = ScimagParser::new();
+        let page = RESULTS_PAGE.replace(
+            "Nanometre-scale thermometry in a living cell",
+            "Too many requests: rate limiting",
+        );
+        let document = Html::parse_document(&format!("<h1>Rate limiting</h1>{}", page));
+        assert_eq!(parser.detect_interstitial(&document), None);
+        assert_eq!(
+            parser.detect_interstitial(&Html::parse_document(
+                "<title>Just a moment...</title><div id=challenge-running></div>"
+            )),
+            Some(LibgenError::ChallengeError)
+        );
+    }
+
+    #[test]
+    fn parse_article_page() {
+        let parser = ScimagParser::new();
+        let document = Html::parse_document(
+            r#"<table><tr><td>DOI:</td><td>10.1038/nature12373</td></tr>
+            <tr><td>Title:</td><td>Nanometre-scale thermometry in a living cell</td></tr>
+            <tr><td>Journal:</td><td>Nature</td></tr>
+            <tr><td>Year:</td><td>2013</td></tr>
+            <tr><td>MD5:</td><td>5EB63BBBE01EEED093CB22BB8F5ACDC3</td></tr></table>"#,
+        );
+
+        let article = parser.parse_article_page(&document).unwrap();
+        assert_eq!(article.year, Some(2013));
+        assert!(article.md5.is_some());
+        assert_eq!(
+            parser.parse_article_page(&Html::parse_document("<p>Not found</p>")),
+            None
+        );
+    }
+
+    #[test]
+    fn scimag_query_path() {
+        assert_eq!(
+            ScimagQuery::title("living cell")
+                .in_journal("Nature")
+                .path(),
+            "scimag/?q=living%20cell%20Nature"
+        );
+    }
+
+    #[test]
+    fn article_paths_are_encoded() {
+        let doi: Doi = "10.1002/(SICI)1097-4571#1".parse().unwrap();
+        assert_eq!(article_path(&doi), "scimag/10.1002/%28sici%291097-4571%231");
+    }
+}
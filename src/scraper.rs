@@ -1,18 +1,44 @@
-use reqwest::{Client, Error, Response, StatusCode};
+use reqwest::{Client, StatusCode};
 use scraper::Html;
-use std::{fmt, thread, time::Duration};
-use urlencoding::encode;
+use std::{fmt, time::Duration};
 
 use crate::{
-    book::LibgenBook,
-    downloader::{DownloadError, Downloader},
+    api::{api_path, parse_api_response, BookMetadata, JSON_BATCH_SIZE},
+    book::{BookDetails, LibgenBook, Section},
+    cache::{CacheConfig, CachePolicy, ResponseCache},
+    downloader::{DownloadError, DownloadedFile, Downloader, DOWNLOAD_PAGE_URL},
+    fiction::{FictionParser, FictionQuery},
+    filename::FilenameTemplate,
+    identifiers::{Doi, LibgenId, Md5},
+    ipfs::Cid,
+    parser::{Mirror, ResultParser, SearchColumn},
     processor::Processor,
+    proxy::{ProxyConfig, ProxyError},
+    scimag::{article_path, LibgenArticle, ScimagParser, ScimagQuery},
 };
 
 const MAX_RETRIES: usize = 3;
 const TIMEOUT_DURATION: u64 = 15;
 const LIBGEN_MIRRORS: [&str; 3] = ["is", "rs", "st"];
+const RECENTLY_ADDED_PATH: &str = "search.php?mode=last";
 
+/// libgen.is, .rs and .st, which share one layout
+pub fn default_mirrors() -> Vec<Mirror> {
+    LIBGEN_MIRRORS
+        .iter()
+        .map(|tld| Mirror::new(&format!("https://www.libgen.{}", tld), Processor::new()))
+        .collect()
+}
+
+/// The fiction sections of libgen.is, .rs and .st
+pub fn default_fiction_mirrors() -> Vec<Mirror> {
+    LIBGEN_MIRRORS
+        .iter()
+        .map(|tld| Mirror::new(&format!("https://www.libgen.{}", tld), FictionParser::new()))
+        .collect()
+}
+
+/// Errors that can occur while searching libgen
 #[derive(Debug, PartialEq)]
 pub enum LibgenError {
     /// Connection error while collecting data.
@@ -25,6 +51,14 @@ pub enum LibgenError {
     NetworkError,
     /// Error encountered while parsing collected data.
     ParsingError,
+    /// The mirror served a Cloudflare or captcha challenge instead of the page.
+    ChallengeError,
+    /// The mirror asked us to slow down.
+    RateLimitedError,
+    /// The page markup no longer looks like what the parser expects.
+    LayoutChanged(String),
+    /// The page is not cached and the client is offline.
+    NotCachedError,
 }
 
 impl fmt::Display for LibgenError {
@@ -35,6 +69,10 @@ impl fmt::Display for LibgenError {
             LibgenError::NotFoundError => "NotFoundError",
             LibgenError::NetworkError => "NetworkError",
             LibgenError::ParsingError => "ParsingError",
+            LibgenError::ChallengeError => "ChallengeError",
+            LibgenError::RateLimitedError => "RateLimitedError",
+            LibgenError::NotCachedError => "NotCachedError",
+            LibgenError::LayoutChanged(details) => return write!(f, "LayoutChanged: {}", details),
         };
         write!(f, "{}", error_str)
     }
@@ -44,8 +82,114 @@ impl fmt::Display for LibgenError {
 pub struct LibgenClient {
     // The request client
     client: Client,
-    processor: Processor,
+    mirrors: Vec<Mirror>,
+    fiction_mirrors: Vec<Mirror>,
+    download_page: Mirror,
+    scimag: ScimagParser,
     downloader: Downloader,
+    cache: Option<ResponseCache>,
+}
+
+/// A page as fetched or read from the cache
+struct Page {
+    status: StatusCode,
+    body: String,
+    cached: bool,
+}
+
+impl Default for LibgenClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Configures a [`LibgenClient`] before it is created
+#[derive(Debug, Clone, Default)]
+pub struct LibgenClientBuilder {
+    proxy: Option<ProxyConfig>,
+    download_path: Option<String>,
+    mirrors: Vec<Mirror>,
+    fiction_mirrors: Vec<Mirror>,
+    download_page: Option<Mirror>,
+    cache: Option<CacheConfig>,
+}
+
+impl LibgenClientBuilder {
+    /// Sends searches and downloads through the given proxies
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Uses the proxies from `ALL_PROXY`, `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY`
+    pub fn proxy_from_env(self) -> Self {
+        self.proxy(ProxyConfig::from_env())
+    }
+
+    /// Sets the directory books are downloaded into
+    pub fn download_path(mut self, download_path: String) -> Self {
+        self.download_path = Some(download_path);
+        self
+    }
+
+    /// Searches this mirror, in the order added, instead of the [`default_mirrors`]
+    pub fn mirror(mut self, mirror: Mirror) -> Self {
+        self.mirrors.push(mirror);
+        self
+    }
+
+    /// Searches fiction on this mirror, in the order added, instead of the
+    /// [`default_fiction_mirrors`]
+    pub fn fiction_mirror(mut self, mirror: Mirror) -> Self {
+        self.fiction_mirrors.push(mirror);
+        self
+    }
+
+    /// Reads IPFS CIDs and the links of fiction books from this site instead of library.lol
+    pub fn download_page(mut self, mirror: Mirror) -> Self {
+        self.download_page = Some(mirror);
+        self
+    }
+
+    /// Keeps search and detail pages on disk, so reruns don't fetch them again
+    pub fn cache(mut self, config: CacheConfig) -> Self {
+        self.cache = Some(config);
+        self
+    }
+
+    /// Creates the client, search and download share one connection pool
+    pub fn build(self) -> Result<LibgenClient, ProxyError> {
+        let mut builder = Client::builder();
+        if let Some(proxy) = &self.proxy {
+            builder = proxy.apply(builder)?;
+        }
+        let client = builder
+            .build()
+            .map_err(|err| ProxyError::ClientError(err.to_string()))?;
+
+        let download_page = self
+            .download_page
+            .unwrap_or_else(|| Mirror::new(DOWNLOAD_PAGE_URL, Processor::new()));
+        let mut downloader = Downloader::with_client(client.clone(), self.download_path);
+        downloader.set_download_page(download_page.clone());
+        Ok(LibgenClient {
+            downloader,
+            client,
+            mirrors: if self.mirrors.is_empty() {
+                default_mirrors()
+            } else {
+                self.mirrors
+            },
+            fiction_mirrors: if self.fiction_mirrors.is_empty() {
+                default_fiction_mirrors()
+            } else {
+                self.fiction_mirrors
+            },
+            download_page,
+            scimag: ScimagParser::new(),
+            cache: self.cache.map(ResponseCache::new),
+        })
+    }
 }
 
 impl LibgenClient {
@@ -53,72 +197,453 @@ impl LibgenClient {
     pub fn new() -> LibgenClient {
         LibgenClient {
             client: Client::new(),
-            processor: Processor::new(),
+            mirrors: default_mirrors(),
+            fiction_mirrors: default_fiction_mirrors(),
+            download_page: Mirror::new(DOWNLOAD_PAGE_URL, Processor::new()),
+            scimag: ScimagParser::new(),
             downloader: Downloader::new(None),
+            cache: None,
         }
     }
+    /// Configure a client, e.g. to use a proxy
+    pub fn builder() -> LibgenClientBuilder {
+        LibgenClientBuilder::default()
+    }
+    /// The downloader used by [`LibgenClient::download_book`], e.g. to build a queue
+    pub fn downloader(&self) -> &Downloader {
+        &self.downloader
+    }
+    /// Changes the directory books are downloaded into
     pub fn set_download_path(&mut self, new_path: String) {
         self.downloader.change_download_path(new_path);
     }
-    pub fn download_book(self, book: &LibgenBook) -> Result<(), DownloadError> {
-        self.downloader.download(book)
+    /// Changes how downloaded books are named, see [`FilenameTemplate`]
+    pub fn set_filename_template(&mut self, template: FilenameTemplate) {
+        self.downloader.set_filename_template(template);
+    }
+    /// Downloads a book found through a search, returning where it was saved
+    ///
+    /// Only borrows the client, so many downloads can be awaited at once. Fiction
+    /// search results lack the id the direct link needs, so for those the link is
+    /// read from the books download page instead, see [`Downloader::has_direct_link`].
+    pub async fn download_book(&self, book: &LibgenBook) -> Result<DownloadedFile, DownloadError> {
+        self.downloader.download(book).await
+    }
+    /// Downloads a book over IPFS, using the CID from its library.lol page
+    pub async fn download_book_via_ipfs(
+        &self,
+        book: &LibgenBook,
+    ) -> Result<DownloadedFile, DownloadError> {
+        let cid = self
+            .fetch_ipfs_cid(book)
+            .await
+            .map_err(|err| DownloadError::ConnectionError(err.to_string()))?
+            .ok_or_else(|| DownloadError::DownloadError("No IPFS CID listed".to_string()))?;
+        self.downloader.download_from_ipfs(book, &cid).await
+    }
+    /// The mirrors searched, in order
+    pub fn mirrors(&self) -> &[Mirror] {
+        &self.mirrors
+    }
+    /// The mirrors searched for fiction, in order
+    pub fn fiction_mirrors(&self) -> &[Mirror] {
+        &self.fiction_mirrors
+    }
+    /// The mirrors holding a books section
+    fn section_mirrors(&self, section: Section) -> &[Mirror] {
+        match section {
+            Section::NonFiction | Section::Scimag => &self.mirrors,
+            Section::Fiction => &self.fiction_mirrors,
+        }
+    }
+    /// Fetches a books library.lol download page
+    async fn fetch_download_page(&self, book: &LibgenBook) -> Result<Html, LibgenError> {
+        let url = self
+            .download_page
+            .url(&self.download_page.parser().download_page_path(book));
+        let page = self.send_request(&url, CachePolicy::Bypass).await?;
+        if page.status != StatusCode::OK {
+            return Err(LibgenError::NetworkError);
+        }
+        Ok(Html::parse_document(&page.body))
+    }
+    /// Looks up the IPFS CID library.lol lists for a book
+    pub async fn fetch_ipfs_cid(&self, book: &LibgenBook) -> Result<Option<Cid>, LibgenError> {
+        let document = self.fetch_download_page(book).await?;
+        Ok(self
+            .download_page
+            .parser()
+            .parse_download_page(&document)
+            .into_iter()
+            .next())
+    }
+    /// Looks up the direct file link library.lol lists for a book
+    pub async fn fetch_download_url(
+        &self,
+        book: &LibgenBook,
+    ) -> Result<Option<String>, LibgenError> {
+        let document = self.fetch_download_page(book).await?;
+        Ok(self.download_page.parser().parse_download_url(&document))
+    }
+    /// Fetches a books detail page for its exact size and hashes, e.g. for [`LibgenBook::magnet_uri`]
+    pub async fn fetch_details(&self, book: &LibgenBook) -> Result<BookDetails, LibgenError> {
+        let (document, mirror) = self
+            .fetch_page(
+                self.section_mirrors(book.section),
+                CachePolicy::Page,
+                |parser| parser.detail_path(&book.libgen_md5),
+                |parser, document| parser.detect_detail_interstitial(document),
+            )
+            .await?;
+        Ok(mirror.parser().parse_detail_page(&document))
     }
     /// Request logic
-    async fn send_request(&self, url: &str) -> Result<Response, Error> {
-        self.client
+    ///
+    /// Pages cached under `policy` are served without a request. Pages are only
+    /// cached by [`LibgenClient::fetch_from_mirrors`].
+    async fn send_request(&self, url: &str, policy: CachePolicy) -> Result<Page, LibgenError> {
+        if let Some(cache) = &self.cache {
+            if let Some(body) = cache.get(url, policy).await {
+                return Ok(Page {
+                    status: StatusCode::OK,
+                    body,
+                    cached: true,
+                });
+            }
+            if cache.is_offline() {
+                return Err(LibgenError::NotCachedError);
+            }
+        }
+        let response = self
+            .client
             .get(url)
             .timeout(Duration::from_secs(TIMEOUT_DURATION))
             .send()
             .await
+            .map_err(|_| LibgenError::ConnectionError)?;
+        Ok(Page {
+            status: response.status(),
+            body: response
+                .text()
+                .await
+                .map_err(|_| LibgenError::ParsingError)?,
+            cached: false,
+        })
     }
     /// Search for a book based on its title
     pub async fn search_book_by_title(
         &self,
         title: &str,
     ) -> Result<Option<LibgenBook>, LibgenError> {
-        let encoded_title = encode(&title);
-        // struct impl new client
+        let (document, mirror) = self
+            .fetch_document(&self.mirrors, CachePolicy::Search, |parser| {
+                parser.search_path(title)
+            })
+            .await?;
+        mirror.parser().parse_search_page(&document, title)
+    }
+
+    /// The latest uploads to the main collection, newest first
+    ///
+    /// See [`crate::feed::RecentFeed`] to only get what is new since the last look.
+    /// The feed is never cached.
+    pub async fn recently_added(&self) -> Result<Vec<LibgenBook>, LibgenError> {
+        self.recently_added_page(1).await
+    }
+
+    /// Like [`LibgenClient::recently_added`], for the older uploads on later pages
+    ///
+    /// Pages are counted from 1.
+    pub async fn recently_added_page(&self, page: u32) -> Result<Vec<LibgenBook>, LibgenError> {
+        let (document, mirror) = self
+            .fetch_document(&self.mirrors, CachePolicy::Bypass, |_| {
+                format!("{}&page={}", RECENTLY_ADDED_PATH, page)
+            })
+            .await?;
+        mirror.parser().parse_search_results(&document)
+    }
+
+    /// Looks a book up by its MD5, in the main collection and then in fiction
+    ///
+    /// Only a result with exactly this MD5 is returned, titles play no part. Fiction
+    /// results come without their id, see [`Downloader::has_direct_link`].
+    pub async fn get_by_md5(&self, md5: &Md5) -> Result<Option<LibgenBook>, LibgenError> {
+        // A section that fails doesn't stop us from looking in the other
+        let mut first_error = None;
+        for mirrors in [&self.mirrors, &self.fiction_mirrors] {
+            let found = match self
+                .fetch_document(mirrors, CachePolicy::Search, |parser| {
+                    parser.md5_search_path(md5)
+                })
+                .await
+            {
+                Ok((document, mirror)) => mirror.parser().parse_search_results(&document),
+                Err(err) => Err(err),
+            };
+            match found {
+                Ok(books) => {
+                    if let Some(book) = books.into_iter().find(|book| &book.libgen_md5 == md5) {
+                        return Ok(Some(book));
+                    }
+                }
+                Err(err) => {
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(None),
+        }
+    }
+
+    /// Searches one column of the main collection, returning every book on the
+    /// first results page, newest first
+    pub async fn search(
+        &self,
+        column: SearchColumn,
+        text: &str,
+    ) -> Result<Vec<LibgenBook>, LibgenError> {
+        let (document, mirror) = self
+            .fetch_document(&self.mirrors, CachePolicy::Search, |parser| {
+                parser.column_search_path(column, text)
+            })
+            .await?;
+        mirror.parser().parse_search_results(&document)
+    }
+
+    /// Searches the fiction section, returning every book on the first results page
+    pub async fn search_fiction(
+        &self,
+        query: &FictionQuery,
+    ) -> Result<Vec<LibgenBook>, LibgenError> {
+        let (document, mirror) = self
+            .fetch_document(&self.fiction_mirrors, CachePolicy::Search, |_| query.path())
+            .await?;
+        mirror.parser().parse_search_results(&document)
+    }
+
+    /// Fetches metadata for libgen ids from the JSON API, in batches
+    ///
+    /// Unknown ids are left out of the result.
+    pub async fn fetch_by_ids(&self, ids: &[u64]) -> Result<Vec<BookMetadata>, LibgenError> {
+        let mut books = Vec::new();
+        for batch in ids.chunks(JSON_BATCH_SIZE) {
+            let (metadata, _) = self
+                .fetch_from_mirrors(
+                    &self.mirrors,
+                    CachePolicy::Page,
+                    |_| api_path(batch),
+                    |parser, body| {
+                        parse_api_response(body).map_err(|_| {
+                            parser
+                                .detect_interstitial(&Html::parse_document(body))
+                                .unwrap_or(LibgenError::ParsingError)
+                        })
+                    },
+                )
+                .await?;
+            books.extend(metadata);
+        }
+        Ok(books)
+    }
+
+    /// Fills in what search results are missing from the JSON API, see [`BookMetadata::enrich`]
+    ///
+    /// Only books with a known id are looked up.
+    pub async fn enrich_books(&self, books: &mut [LibgenBook]) -> Result<(), LibgenError> {
+        let ids: Vec<u64> = books
+            .iter()
+            .filter(|book| book.section == Section::NonFiction)
+            .filter_map(|book| book.libgen_id.map(LibgenId::get))
+            .collect();
+        let metadata = self.fetch_by_ids(&ids).await?;
+        for book in books.iter_mut() {
+            if let Some(found) = metadata
+                .iter()
+                .find(|found| found.book.libgen_md5 == book.libgen_md5)
+            {
+                found.enrich(book);
+            }
+        }
+        Ok(())
+    }
+
+    /// Searches scientific articles by title and journal
+    ///
+    /// Returns the articles on the first results page that pass the journal filter.
+    pub async fn search_articles(
+        &self,
+        query: &ScimagQuery,
+    ) -> Result<Vec<LibgenArticle>, LibgenError> {
+        let (document, _) = self
+            .fetch_page(
+                &self.mirrors,
+                CachePolicy::Search,
+                |_| query.path(),
+                |_, document| self.scimag.detect_interstitial(document),
+            )
+            .await?;
+        Ok(self
+            .scimag
+            .search_results_in_document(&document)?
+            .into_iter()
+            .filter(|article| query.matches(article))
+            .collect())
+    }
+
+    /// Looks up a scientific article by its DOI
+    pub async fn get_article_by_doi(
+        &self,
+        doi: &Doi,
+    ) -> Result<Option<LibgenArticle>, LibgenError> {
+        let (document, _) = self
+            .fetch_page(
+                &self.mirrors,
+                CachePolicy::Page,
+                |_| article_path(doi),
+                |_, document| self.scimag.detect_interstitial(document),
+            )
+            .await?;
+        Ok(self.scimag.parse_article_page(&document))
+    }
+
+    /// Downloads a scientific article, looking up its MD5 first when a search left it out
+    pub async fn download_article(
+        &self,
+        article: &LibgenArticle,
+    ) -> Result<DownloadedFile, DownloadError> {
+        if article.md5.is_some() {
+            return self.downloader.download_article(article).await;
+        }
+        let found = self
+            .get_article_by_doi(&article.doi)
+            .await
+            .map_err(|err| DownloadError::ConnectionError(err.to_string()))?
+            .ok_or_else(|| {
+                DownloadError::DownloadError(format!("Article {} not found", article.doi))
+            })?;
+        self.downloader
+            .download_article(&LibgenArticle {
+                md5: found.md5,
+                ..article.clone()
+            })
+            .await
+    }
+
+    /// Fetches a page from the first of `mirrors` that serves it, along with that mirror
+    ///
+    /// `path` builds the path for each mirrors parser.
+    async fn fetch_document<'a>(
+        &self,
+        mirrors: &'a [Mirror],
+        policy: CachePolicy,
+        path: impl Fn(&dyn ResultParser) -> String,
+    ) -> Result<(Html, &'a Mirror), LibgenError> {
+        self.fetch_page(mirrors, policy, path, |parser, document| {
+            parser.detect_interstitial(document)
+        })
+        .await
+    }
+
+    /// Like [`LibgenClient::fetch_document`], telling real pages from interstitials with `detect`
+    async fn fetch_page<'a>(
+        &self,
+        mirrors: &'a [Mirror],
+        policy: CachePolicy,
+        path: impl Fn(&dyn ResultParser) -> String,
+        detect: impl Fn(&dyn ResultParser, &Html) -> Option<LibgenError>,
+    ) -> Result<(Html, &'a Mirror), LibgenError> {
+        self.fetch_from_mirrors(mirrors, policy, path, |parser, body| {
+            let document = Html::parse_document(body);
+            match detect(parser, &document) {
+                Some(err) => Err(err),
+                None => Ok(document),
+            }
+        })
+        .await
+    }
+
+    /// Requests `path` from the first of `mirrors` that answers, reading the body with `read`
+    ///
+    /// Mirrors that are overloaded, rate limit us or answer with a challenge page
+    /// are skipped, as are those whose body `read` rejects with
+    /// [`LibgenError::ChallengeError`] or [`LibgenError::RateLimitedError`]. Once
+    /// every mirror was tried we wait before starting over. Bodies are cached
+    /// according to `policy`.
+    async fn fetch_from_mirrors<'a, T>(
+        &self,
+        mirrors: &'a [Mirror],
+        policy: CachePolicy,
+        path: impl Fn(&dyn ResultParser) -> String,
+        read: impl Fn(&dyn ResultParser, &str) -> Result<T, LibgenError>,
+    ) -> Result<(T, &'a Mirror), LibgenError> {
         let mut retries = 0;
         let mut retries_domain = 0;
+        let mut last_error = LibgenError::TimeoutError;
 
         while retries <= MAX_RETRIES {
-            let libgen_search_url: String = format!("https://www.libgen.{}/search.php?&req={}&phrase=1&view=simple&column=title&sort=year&sortmode=DESC", LIBGEN_MIRRORS[retries_domain], encoded_title);
-
-            let response = match self.send_request(&libgen_search_url).await {
-                Ok(response) => {
-                    // We need to be gentlemen and not spam libgen
-                    if response.status() == StatusCode::SERVICE_UNAVAILABLE {
-                        retries += 1;
-                        retries_domain = if retries_domain < LIBGEN_MIRRORS.len() - 1 {
-                            retries_domain + 1
-                        } else {
-                            thread::sleep(Duration::from_secs(TIMEOUT_DURATION));
-                            0
-                        };
+            let mirror = &mirrors[retries_domain];
+            let url = mirror.url(&path(mirror.parser()));
+            let page = match self.send_request(&url, policy).await {
+                // Offline, another mirror may have the page cached
+                Err(LibgenError::NotCachedError) if retries_domain < mirrors.len() - 1 => {
+                    retries_domain += 1;
+                    continue;
+                }
+                result => result?,
+            };
 
-                        continue;
+            let status = page.status;
+            last_error = match status {
+                StatusCode::OK => {
+                    // Stored before reading, the parsed value may not be held across an await
+                    if let (Some(cache), false) = (&self.cache, page.cached) {
+                        if policy != CachePolicy::Bypass {
+                            cache.put(&url, &page.body).await;
+                        }
+                    }
+                    let err = match read(mirror.parser(), &page.body) {
+                        Ok(value) => return Ok((value, mirror)),
+                        Err(err) => err,
+                    };
+                    // Challenge pages and bodies we can't read aren't worth keeping
+                    if let Some(cache) = &self.cache {
+                        cache.remove(&url).await;
+                    }
+                    match err {
+                        LibgenError::ChallengeError | LibgenError::RateLimitedError => err,
+                        err => return Err(err),
                     }
-                    response
                 }
-                Err(_) => {
-                    return Err(LibgenError::ConnectionError);
+                // Challenge pages also come back as 403 or 429
+                StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS => {
+                    match mirror
+                        .parser()
+                        .detect_interstitial(&Html::parse_document(&page.body))
+                    {
+                        Some(err) => err,
+                        None if status == StatusCode::TOO_MANY_REQUESTS => {
+                            LibgenError::RateLimitedError
+                        }
+                        None => return Err(LibgenError::NetworkError),
+                    }
                 }
+                StatusCode::SERVICE_UNAVAILABLE => LibgenError::TimeoutError,
+                _ => return Err(LibgenError::NetworkError),
             };
 
-            if response.status() == StatusCode::OK {
-                let document = Html::parse_document(
-                    &response
-                        .text()
-                        .await
-                        .map_err(|_| LibgenError::ParsingError)?,
-                );
-                return Ok(self.processor.search_title_in_document(&document, title)?);
-            }
-
-            return Err(LibgenError::NetworkError);
+            // We need to be gentlemen and not spam libgen
+            retries += 1;
+            retries_domain = if retries_domain < mirrors.len() - 1 {
+                retries_domain + 1
+            } else {
+                tokio::time::sleep(Duration::from_secs(TIMEOUT_DURATION)).await;
+                0
+            };
         }
 
-        Err(LibgenError::TimeoutError)
+        Err(last_error)
     }
 
     // Search for a group of titles
@@ -139,6 +664,261 @@ impl LibgenClient {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        book::Author,
+        downloader::tests::{serve_bytes, test_client},
+    };
+
+    #[test]
+    fn client_can_be_shared_between_tasks() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        fn assert_send<T: Send>(_: T) {}
+        assert_send_sync::<LibgenClient>();
+
+        // The requests themselves can be spawned too
+        let client = LibgenClient::new();
+        assert_send(client.search_book_by_title("cats"));
+        assert_send(client.fetch_by_ids(&[3750]));
+    }
+
+    #[tokio::test]
+    async fn builder_proxies_downloads() {
+        let (proxy, targets) = crate::proxy::tests::socks_stand_in(b"hello world").await;
+        let directory = tempfile::tempdir().unwrap();
+        let test_client = LibgenClient::builder()
+            .proxy(ProxyConfig::all(&proxy))
+            .download_path(directory.path().display().to_string())
+            .build()
+            .unwrap();
+
+        let mut downloader = test_client.downloader().clone();
+        downloader.set_hosts(vec!["http://files.libgen.invalid".to_string()]);
+        let book = crate::downloader::tests::book_with_md5("5eb63bbbe01eeed093cb22bb8f5acdc3");
+        downloader.download(&book).await.unwrap();
+
+        assert_eq!(
+            *targets.lock().unwrap(),
+            vec!["files.libgen.invalid".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn reruns_are_served_from_the_cache() {
+        let host = serve_bytes(include_bytes!("../benches/benchmark_page.htm")).await;
+        let directory = tempfile::tempdir().unwrap();
+        let client = |mode| {
+            test_client(&host)
+                .cache(CacheConfig {
+                    mode,
+                    ..CacheConfig::new(directory.path())
+                })
+                .build()
+                .unwrap()
+        };
+
+        let offline = client(crate::cache::CacheMode::Offline);
+        assert_eq!(
+            offline.search(SearchColumn::Title, "cats").await,
+            Err(LibgenError::NotCachedError)
+        );
+        let online = client(crate::cache::CacheMode::ReadWrite)
+            .search(SearchColumn::Title, "cats")
+            .await
+            .unwrap();
+        assert_eq!(
+            offline.search(SearchColumn::Title, "cats").await.unwrap(),
+            online
+        );
+    }
+
+    #[tokio::test]
+    async fn the_feed_bypasses_the_cache() {
+        let host = serve_bytes(include_bytes!("../benches/benchmark_page.htm")).await;
+        let directory = tempfile::tempdir().unwrap();
+        let client = |mode| {
+            test_client(&host)
+                .cache(CacheConfig {
+                    mode,
+                    ..CacheConfig::new(directory.path())
+                })
+                .build()
+                .unwrap()
+        };
+
+        assert!(!client(crate::cache::CacheMode::ReadWrite)
+            .recently_added()
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            client(crate::cache::CacheMode::Offline)
+                .recently_added()
+                .await,
+            Err(LibgenError::NotCachedError)
+        );
+    }
+
+    #[tokio::test]
+    async fn challenge_pages_move_on_to_the_next_mirror() {
+        let challenge = crate::downloader::tests::serve_bytes(
+            br#"<html><head><title>Just a moment...</title></head>
+            <body><div id="challenge-running">Checking your browser</div></body></html>"#,
+        )
+        .await;
+        let page: &'static str = Box::leak(
+            format!(
+                "<html><head><title>Rate limiting</title></head><body><h1>Rate limiting</h1>\
+                 <p>{}</p><p>Size: 1 MB (1 048 576 bytes)</p></body></html>",
+                "A book on what to do about too many requests. ".repeat(60)
+            )
+            .into_boxed_str(),
+        );
+        let normal = crate::downloader::tests::serve_bytes(page.as_bytes()).await;
+        let client = |hosts: [&str; 2]| {
+            LibgenClient::builder()
+                .mirror(Mirror::new(hosts[0], Processor::new()))
+                .mirror(Mirror::new(hosts[1], Processor::new()))
+                .build()
+                .unwrap()
+        };
+        let book = crate::downloader::tests::book_with_md5("5eb63bbbe01eeed093cb22bb8f5acdc3");
+
+        let client = client([&challenge, &normal]);
+        let (_, mirror) = client
+            .fetch_document(client.mirrors(), CachePolicy::Page, |parser| {
+                parser.detail_path(&book.libgen_md5)
+            })
+            .await
+            .unwrap();
+        assert_eq!(mirror.base_url(), normal);
+
+        // A page that only talks about rate limits is served by the first mirror
+        let client = test_client(&normal).build().unwrap();
+        assert_eq!(
+            client.fetch_details(&book).await.unwrap().size_bytes,
+            Some(1048576)
+        );
+    }
+
+    /// A site whose search pages list one md5 per line
+    struct PlainTextParser;
+
+    impl ResultParser for PlainTextParser {
+        fn search_path(&self, title: &str) -> String {
+            format!("find?q={}", title)
+        }
+        fn column_search_path(&self, _column: SearchColumn, text: &str) -> String {
+            self.search_path(text)
+        }
+        fn detail_path(&self, md5: &Md5) -> String {
+            format!("md5/{}", md5)
+        }
+        fn download_page_path(&self, book: &LibgenBook) -> String {
+            format!("get/{}", book.libgen_md5)
+        }
+        fn parse_search_page(
+            &self,
+            html_document: &Html,
+            title: &str,
+        ) -> Result<Option<LibgenBook>, LibgenError> {
+            let text = html_document.root_element().text().collect::<String>();
+            Ok(text
+                .lines()
+                .find_map(|line| line.trim().parse().ok())
+                .map(|libgen_md5| LibgenBook {
+                    title: title.to_owned(),
+                    libgen_md5,
+                    ..crate::downloader::tests::book_with_md5("5eb63bbbe01eeed093cb22bb8f5acdc3")
+                }))
+        }
+        fn parse_search_results(
+            &self,
+            html_document: &Html,
+        ) -> Result<Vec<LibgenBook>, LibgenError> {
+            Ok(self
+                .parse_search_page(html_document, "")?
+                .into_iter()
+                .collect())
+        }
+        fn parse_detail_page(&self, _html_document: &Html) -> BookDetails {
+            BookDetails::default()
+        }
+        fn parse_download_page(&self, _html_document: &Html) -> Vec<Cid> {
+            Vec::new()
+        }
+    }
+
+    #[tokio::test]
+    async fn searches_with_the_mirrors_parser() {
+        let host =
+            crate::downloader::tests::serve_bytes(b"5fa82be26689a4e6f4415ea068d35a9d\n").await;
+        let test_client = LibgenClient::builder()
+            .mirror(Mirror::new(&host, PlainTextParser))
+            .build()
+            .unwrap();
+        assert_eq!(test_client.mirrors().len(), 1);
+
+        let book = test_client
+            .search_book_by_title("cats")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(book.title, "cats");
+        assert_eq!(book.libgen_md5.as_str(), "5fa82be26689a4e6f4415ea068d35a9d");
+    }
+
+    #[tokio::test]
+    async fn get_by_md5_matches_exactly() {
+        let host = serve_bytes(include_bytes!("../benches/benchmark_page.htm")).await;
+        let client = test_client(&host)
+            .fiction_mirror(Mirror::new(&host, FictionParser::new()))
+            .build()
+            .unwrap();
+
+        let md5: Md5 = "D668FF05D1CEAE78CFF1825FAAC398EA".parse().unwrap();
+        let book = client.get_by_md5(&md5).await.unwrap().unwrap();
+        assert_eq!(book.libgen_id, Some(LibgenId(25803)));
+        assert_eq!(book.title, "Performance Evaluation and Benchmarking");
+
+        let unknown: Md5 = "5eb63bbbe01eeed093cb22bb8f5acdc3".parse().unwrap();
+        assert_eq!(client.get_by_md5(&unknown).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn get_by_md5_looks_in_fiction_when_the_main_section_fails() {
+        let fiction = crate::downloader::tests::serve_bytes(
+            br#"<table class="catalog">
+            <tr><th>Author(s)</th><th>Title</th><th>File</th></tr>
+            <tr><td><a href="/fiction/?q=Le+Guin">Le Guin, Ursula K.</a></td>
+            <td><a href="/fiction/2B0A2E0B4F1C4A0E9A3D6C1E8F7B5A41">The Left Hand of Darkness</a></td>
+            <td>EPUB / 310 Kb</td></tr></table>"#,
+        )
+        .await;
+        // Nothing listens on a port that was just closed
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let client = |main: &str| {
+            LibgenClient::builder()
+                .mirror(Mirror::new(main, Processor::new()))
+                .fiction_mirror(Mirror::new(&fiction, FictionParser::new()))
+                .build()
+                .unwrap()
+        };
+        let broken = client(&format!("http://{}", closed));
+
+        let md5: Md5 = "2b0a2e0b4f1c4a0e9a3d6c1e8f7b5a41".parse().unwrap();
+        let book = broken.get_by_md5(&md5).await.unwrap().unwrap();
+        assert_eq!(book.title, "The Left Hand of Darkness");
+
+        // Not found in fiction, the main sections error is all we know
+        let unknown: Md5 = "5eb63bbbe01eeed093cb22bb8f5acdc3".parse().unwrap();
+        assert_eq!(
+            broken.get_by_md5(&unknown).await,
+            Err(LibgenError::ConnectionError)
+        );
+    }
 
     #[test]
     fn search_book_with_single_author() {
@@ -147,12 +927,16 @@ mod tests {
         let generic_book = "Python for Security and Networking".to_string();
 
         let valid_result = LibgenBook {
-            libgen_id: 3759134,
-            libgen_md5: "6bed397b612b9e3994a7dc2d6b5440ba".to_owned(),
+            libgen_id: Some(LibgenId(3759134)),
+            libgen_md5: "6bed397b612b9e3994a7dc2d6b5440ba".parse().unwrap(),
             file_type: "epub".to_owned(),
             title: "Python for Security and Networking: Leverage Python modules and tools in securing your network and applications".to_owned(),
-            authors: vec!["José Manuel Ortega".to_string()],
+            authors: vec![Author::new("José Manuel Ortega")],
             publisher: "Packt Publishing".to_owned(),
+            isbns: vec![],
+            year: Some(2023),
+            series: None,
+            section: Section::NonFiction,
         };
         let result = test_client.search_book_by_title(&generic_book);
 
@@ -161,6 +945,11 @@ mod tests {
                 // Assert equality
                 match actual_result {
                     Some(result) => {
+                        // ISBN listings are edited by librarians, don't pin them
+                        let result = LibgenBook {
+                            isbns: vec![],
+                            ..result
+                        };
                         assert_eq!(valid_result, result);
                     }
                     None => panic!("search result was None"),
@@ -180,16 +969,20 @@ mod tests {
         let coauthored_book = "Abstract and concrete categories: the joy of cats".to_string();
 
         let valid_cat_result = LibgenBook {
-            libgen_id: 3750,
-            libgen_md5: "5fa82be26689a4e6f4415ea068d35a9d".to_owned(),
+            libgen_id: Some(LibgenId(3750)),
+            libgen_md5: "5fa82be26689a4e6f4415ea068d35a9d".parse().unwrap(),
             file_type: "pdf".to_owned(),
             title: "Abstract and concrete categories: the joy of cats".to_owned(),
             authors: vec![
-                "Jiri Adamek".to_string(),
-                " Horst Herrlich".to_string(),
-                " George E. Strecker".to_string(),
+                Author::new("Jiri Adamek"),
+                Author::new("Horst Herrlich"),
+                Author::new("George E. Strecker"),
             ],
             publisher: "Wiley-Interscience".to_owned(),
+            isbns: vec![],
+            year: Some(1990),
+            series: None,
+            section: Section::NonFiction,
         };
 
         let result = test_client.search_book_by_title(&coauthored_book);
@@ -199,6 +992,11 @@ mod tests {
                 // Assert equality
                 match actual_result {
                     Some(result) => {
+                        // ISBN listings are edited by librarians, don't pin them
+                        let result = LibgenBook {
+                            isbns: vec![],
+                            ..result
+                        };
                         assert_eq!(valid_cat_result, result);
                     }
                     None => panic!("search result was None"),
@@ -0,0 +1,509 @@
+use core::fmt;
+use std::{collections::HashSet, future::Future, pin::Pin, sync::Arc};
+
+use crate::{
+    api::BookMetadata,
+    book::{BookDetails, LibgenBook, Section},
+    catalog::{CatalogError, LocalCatalog},
+    identifiers::{LibgenId, Md5},
+    parser::SearchColumn,
+    scraper::{LibgenClient, LibgenError},
+};
+
+/// What the methods of a [`BookSource`] return
+pub type SourceFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, SourceError>> + Send + 'a>>;
+
+/// Errors from asking a [`BookSource`]
+#[derive(Debug)]
+pub enum SourceError {
+    /// Asking libgen online failed.
+    SearchError(LibgenError),
+    /// Reading the local catalog failed.
+    CatalogError(CatalogError),
+    /// The source can't answer this kind of request.
+    UnsupportedError(String),
+    /// The source doesn't know the book asked for.
+    NotFound,
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceError::SearchError(err) => write!(f, "SearchError: {}", err),
+            SourceError::CatalogError(err) => write!(f, "CatalogError: {}", err),
+            SourceError::UnsupportedError(err) => write!(f, "UnsupportedError: {}", err),
+            SourceError::NotFound => write!(f, "NotFound"),
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+impl From<LibgenError> for SourceError {
+    fn from(error: LibgenError) -> Self {
+        SourceError::SearchError(error)
+    }
+}
+
+impl From<CatalogError> for SourceError {
+    fn from(error: CatalogError) -> Self {
+        SourceError::CatalogError(error)
+    }
+}
+
+/// Somewhere books can be looked up, e.g. the libgen mirrors or a [`LocalCatalog`]
+///
+/// Sources can be swapped for one another or combined with a [`SourceChain`].
+pub trait BookSource: Send + Sync {
+    /// Searches one column, returning books in the order the source ranks them
+    fn search<'a>(
+        &'a self,
+        column: SearchColumn,
+        text: &'a str,
+    ) -> SourceFuture<'a, Vec<LibgenBook>>;
+    /// Looks a book up by its exact MD5
+    fn get_by_md5<'a>(&'a self, md5: &'a Md5) -> SourceFuture<'a, Option<LibgenBook>>;
+    /// Exact size and hashes of a book
+    fn details<'a>(&'a self, book: &'a LibgenBook) -> SourceFuture<'a, BookDetails>;
+    /// Urls the file of a book can be downloaded from, best first
+    fn download_links<'a>(&'a self, book: &'a LibgenBook) -> SourceFuture<'a, Vec<String>>;
+}
+
+impl<T: BookSource + ?Sized> BookSource for Arc<T> {
+    fn search<'a>(
+        &'a self,
+        column: SearchColumn,
+        text: &'a str,
+    ) -> SourceFuture<'a, Vec<LibgenBook>> {
+        (**self).search(column, text)
+    }
+    fn get_by_md5<'a>(&'a self, md5: &'a Md5) -> SourceFuture<'a, Option<LibgenBook>> {
+        (**self).get_by_md5(md5)
+    }
+    fn details<'a>(&'a self, book: &'a LibgenBook) -> SourceFuture<'a, BookDetails> {
+        (**self).details(book)
+    }
+    fn download_links<'a>(&'a self, book: &'a LibgenBook) -> SourceFuture<'a, Vec<String>> {
+        (**self).download_links(book)
+    }
+}
+
+/// The mirror download link, for books whose id is known
+fn direct_link(book: &LibgenBook) -> Vec<String> {
+    book.build_direct_download_url().into_iter().collect()
+}
+
+/// The libgen mirrors, searched through their HTML pages
+impl BookSource for LibgenClient {
+    fn search<'a>(
+        &'a self,
+        column: SearchColumn,
+        text: &'a str,
+    ) -> SourceFuture<'a, Vec<LibgenBook>> {
+        Box::pin(async move { Ok(LibgenClient::search(self, column, text).await?) })
+    }
+    fn get_by_md5<'a>(&'a self, md5: &'a Md5) -> SourceFuture<'a, Option<LibgenBook>> {
+        Box::pin(async move { Ok(LibgenClient::get_by_md5(self, md5).await?) })
+    }
+    fn details<'a>(&'a self, book: &'a LibgenBook) -> SourceFuture<'a, BookDetails> {
+        Box::pin(async move { Ok(self.fetch_details(book).await?) })
+    }
+    /// The mirror link when the id is known, then the one on the library.lol page
+    fn download_links<'a>(&'a self, book: &'a LibgenBook) -> SourceFuture<'a, Vec<String>> {
+        Box::pin(async move {
+            let mut links = direct_link(book);
+            match self.fetch_download_url(book).await {
+                Ok(link) => links.extend(link),
+                // The download page is only needed when there is no direct link
+                Err(err) if links.is_empty() => return Err(err.into()),
+                Err(_) => {}
+            }
+            Ok(links)
+        })
+    }
+}
+
+/// Libgens JSON API, which knows books by their id only
+///
+/// It has no search, so it is mostly useful in a [`SourceChain`] for the details of
+/// books found elsewhere.
+pub struct ApiSource {
+    client: Arc<LibgenClient>,
+}
+
+impl ApiSource {
+    /// Asks the JSON API of the clients mirrors
+    pub fn new(client: Arc<LibgenClient>) -> ApiSource {
+        ApiSource { client }
+    }
+
+    async fn metadata(&self, id: Option<LibgenId>) -> Result<Option<BookMetadata>, SourceError> {
+        let Some(id) = id else {
+            return Ok(None);
+        };
+        Ok(self.client.fetch_by_ids(&[id.get()]).await?.pop())
+    }
+}
+
+impl BookSource for ApiSource {
+    fn search<'a>(
+        &'a self,
+        _column: SearchColumn,
+        _text: &'a str,
+    ) -> SourceFuture<'a, Vec<LibgenBook>> {
+        Box::pin(async {
+            Err(SourceError::UnsupportedError(
+                "the JSON API has no search".to_owned(),
+            ))
+        })
+    }
+    fn get_by_md5<'a>(&'a self, _md5: &'a Md5) -> SourceFuture<'a, Option<LibgenBook>> {
+        Box::pin(async {
+            Err(SourceError::UnsupportedError(
+                "the JSON API looks books up by id".to_owned(),
+            ))
+        })
+    }
+    fn details<'a>(&'a self, book: &'a LibgenBook) -> SourceFuture<'a, BookDetails> {
+        Box::pin(async move {
+            match self.metadata(book.libgen_id).await? {
+                Some(metadata) if metadata.book.libgen_md5 == book.libgen_md5 => {
+                    Ok(metadata.details)
+                }
+                _ => Err(SourceError::NotFound),
+            }
+        })
+    }
+    fn download_links<'a>(&'a self, book: &'a LibgenBook) -> SourceFuture<'a, Vec<String>> {
+        Box::pin(async move {
+            // The API covers the main collection only
+            if book.section != Section::NonFiction {
+                return Ok(Vec::new());
+            }
+            Ok(direct_link(book))
+        })
+    }
+}
+
+/// Books imported from libgens database dumps, without touching the network
+impl BookSource for LocalCatalog {
+    fn search<'a>(
+        &'a self,
+        column: SearchColumn,
+        text: &'a str,
+    ) -> SourceFuture<'a, Vec<LibgenBook>> {
+        Box::pin(async move { Ok(LocalCatalog::search(self, column, text).await?) })
+    }
+    fn get_by_md5<'a>(&'a self, md5: &'a Md5) -> SourceFuture<'a, Option<LibgenBook>> {
+        Box::pin(async move { Ok(LocalCatalog::get_by_md5(self, md5).await?) })
+    }
+    fn details<'a>(&'a self, book: &'a LibgenBook) -> SourceFuture<'a, BookDetails> {
+        Box::pin(async move {
+            // Books added from search results have no details, a later source may
+            match self.record_by_md5(&book.libgen_md5).await? {
+                Some(record) if record.details != BookDetails::default() => Ok(record.details),
+                _ => Err(SourceError::NotFound),
+            }
+        })
+    }
+    fn download_links<'a>(&'a self, book: &'a LibgenBook) -> SourceFuture<'a, Vec<String>> {
+        Box::pin(async move { Ok(direct_link(book)) })
+    }
+}
+
+/// How a [`SourceChain`] searches
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChainMode {
+    /// Asks every source and merges what they found
+    #[default]
+    Merge,
+    /// Stops at the first source that finds anything, e.g. local first, then online
+    FirstHit,
+}
+
+/// Asks several sources in order, as one
+///
+/// Results are de-duplicated by MD5, the earlier source's copy of a book wins.
+/// A source that fails is passed over as long as another one answers, otherwise
+/// the first error is returned.
+#[derive(Clone, Default)]
+pub struct SourceChain {
+    sources: Vec<Arc<dyn BookSource>>,
+    mode: ChainMode,
+}
+
+impl SourceChain {
+    /// Creates an empty chain that merges results
+    pub fn new() -> SourceChain {
+        SourceChain::default()
+    }
+
+    /// Asks `source` after the ones added before
+    pub fn then(mut self, source: impl BookSource + 'static) -> Self {
+        self.sources.push(Arc::new(source));
+        self
+    }
+
+    /// Changes how searches combine the sources
+    pub fn mode(mut self, mode: ChainMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// The sources, in the order they are asked
+    pub fn sources(&self) -> &[Arc<dyn BookSource>] {
+        &self.sources
+    }
+
+    fn no_sources() -> SourceError {
+        SourceError::UnsupportedError("the chain has no sources".to_owned())
+    }
+}
+
+impl BookSource for SourceChain {
+    fn search<'a>(
+        &'a self,
+        column: SearchColumn,
+        text: &'a str,
+    ) -> SourceFuture<'a, Vec<LibgenBook>> {
+        Box::pin(async move {
+            let mut seen = HashSet::new();
+            let mut books = Vec::new();
+            let mut answered = false;
+            let mut first_error = None;
+
+            for source in &self.sources {
+                match source.search(column, text).await {
+                    Ok(found) => {
+                        answered = true;
+                        books.extend(
+                            found
+                                .into_iter()
+                                .filter(|book| seen.insert(book.libgen_md5.clone())),
+                        );
+                        if self.mode == ChainMode::FirstHit && !books.is_empty() {
+                            break;
+                        }
+                    }
+                    Err(err) => {
+                        first_error.get_or_insert(err);
+                    }
+                }
+            }
+
+            match first_error {
+                Some(err) if !answered => Err(err),
+                None if !answered => Err(SourceChain::no_sources()),
+                _ => Ok(books),
+            }
+        })
+    }
+
+    fn get_by_md5<'a>(&'a self, md5: &'a Md5) -> SourceFuture<'a, Option<LibgenBook>> {
+        Box::pin(async move {
+            let mut answered = false;
+            let mut first_error = None;
+            for source in &self.sources {
+                match source.get_by_md5(md5).await {
+                    Ok(Some(book)) => return Ok(Some(book)),
+                    Ok(None) => answered = true,
+                    Err(err) => {
+                        first_error.get_or_insert(err);
+                    }
+                }
+            }
+            match first_error {
+                Some(err) if !answered => Err(err),
+                None if !answered => Err(SourceChain::no_sources()),
+                _ => Ok(None),
+            }
+        })
+    }
+
+    fn details<'a>(&'a self, book: &'a LibgenBook) -> SourceFuture<'a, BookDetails> {
+        Box::pin(async move {
+            let mut first_error = None;
+            for source in &self.sources {
+                match source.details(book).await {
+                    Ok(details) => return Ok(details),
+                    Err(err) => {
+                        first_error.get_or_insert(err);
+                    }
+                }
+            }
+            Err(first_error.unwrap_or_else(SourceChain::no_sources))
+        })
+    }
+
+    fn download_links<'a>(&'a self, book: &'a LibgenBook) -> SourceFuture<'a, Vec<String>> {
+        Box::pin(async move {
+            let mut seen = HashSet::new();
+            let mut links = Vec::new();
+            let mut answered = false;
+            let mut first_error = None;
+            for source in &self.sources {
+                match source.download_links(book).await {
+                    Ok(found) => {
+                        answered = true;
+                        links.extend(found.into_iter().filter(|link| seen.insert(link.clone())));
+                    }
+                    Err(err) => {
+                        first_error.get_or_insert(err);
+                    }
+                }
+            }
+            match first_error {
+                Some(err) if !answered => Err(err),
+                None if !answered => Err(SourceChain::no_sources()),
+                _ => Ok(links),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{catalog::CatalogRecord, downloader::tests::book_with_md5};
+    use std::sync::Mutex;
+
+    /// Serves a fixed list of books and counts the searches it answers
+    struct StaticSource {
+        books: Vec<LibgenBook>,
+        searches: Mutex<usize>,
+    }
+
+    impl StaticSource {
+        fn new(md5s: &[&str]) -> Arc<StaticSource> {
+            Arc::new(StaticSource {
+                books: md5s.iter().map(|md5| book_with_md5(md5)).collect(),
+                searches: Mutex::new(0),
+            })
+        }
+    }
+
+    impl BookSource for StaticSource {
+        fn search<'a>(
+            &'a self,
+            _column: SearchColumn,
+            _text: &'a str,
+        ) -> SourceFuture<'a, Vec<LibgenBook>> {
+            *self.searches.lock().unwrap() += 1;
+            Box::pin(async move { Ok(self.books.clone()) })
+        }
+        fn get_by_md5<'a>(&'a self, md5: &'a Md5) -> SourceFuture<'a, Option<LibgenBook>> {
+            Box::pin(async move {
+                Ok(self
+                    .books
+                    .iter()
+                    .find(|book| &book.libgen_md5 == md5)
+                    .cloned())
+            })
+        }
+        fn details<'a>(&'a self, _book: &'a LibgenBook) -> SourceFuture<'a, BookDetails> {
+            Box::pin(async { Err(SourceError::UnsupportedError("no details".to_owned())) })
+        }
+        fn download_links<'a>(&'a self, book: &'a LibgenBook) -> SourceFuture<'a, Vec<String>> {
+            Box::pin(async move { Ok(direct_link(book)) })
+        }
+    }
+
+    const CATS: &str = "5fa82be26689a4e6f4415ea068d35a9d";
+    const HELLO: &str = "5eb63bbbe01eeed093cb22bb8f5acdc3";
+
+    #[tokio::test]
+    async fn merges_by_md5() {
+        let chain = SourceChain::new()
+            .then(StaticSource::new(&[CATS]))
+            .then(StaticSource::new(&[HELLO, CATS]));
+
+        let found = chain.search(SearchColumn::Title, "cats").await.unwrap();
+        assert_eq!(
+            found
+                .iter()
+                .map(|book| book.libgen_md5.to_string())
+                .collect::<Vec<_>>(),
+            vec![CATS, HELLO]
+        );
+        assert_eq!(chain.download_links(&found[0]).await.unwrap().len(), 1);
+        assert!(matches!(
+            chain.details(&found[0]).await,
+            Err(SourceError::UnsupportedError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn falls_back_in_order() {
+        let local = StaticSource::new(&[]);
+        let online = StaticSource::new(&[HELLO]);
+        let chain = SourceChain::new()
+            .mode(ChainMode::FirstHit)
+            .then(ApiSource::new(Arc::new(LibgenClient::new())))
+            .then(local.clone())
+            .then(online.clone());
+
+        // The API can't search, the empty local source sends us on
+        assert_eq!(
+            chain
+                .search(SearchColumn::Title, "hello")
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            chain
+                .search(SearchColumn::Title, "hello")
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(*online.searches.lock().unwrap(), 2);
+        assert!(chain
+            .get_by_md5(&HELLO.parse().unwrap())
+            .await
+            .unwrap()
+            .is_some());
+        assert!(SourceChain::new()
+            .search(SearchColumn::Title, "hello")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn local_catalog_is_a_source() {
+        let directory = tempfile::tempdir().unwrap();
+        let catalog = LocalCatalog::new(directory.path().join("catalog.jsonl"));
+        let mut book = book_with_md5(CATS);
+        book.title = "Cats".to_owned();
+        catalog.add_books(&[book.clone()]).await.unwrap();
+
+        let source: Arc<dyn BookSource> = Arc::new(catalog);
+        assert_eq!(
+            source.search(SearchColumn::Title, "cats").await.unwrap(),
+            vec![book.clone()]
+        );
+        assert!(matches!(
+            source.details(&book).await,
+            Err(SourceError::NotFound)
+        ));
+
+        // A dump with the books details answers once the catalog without them didn't
+        let details = BookDetails {
+            size_bytes: Some(3076236),
+            ..BookDetails::default()
+        };
+        let dump_path = directory.path().join("dump.jsonl");
+        let record = CatalogRecord {
+            book: book.clone(),
+            language: None,
+            details: details.clone(),
+        };
+        std::fs::write(&dump_path, serde_json::to_string(&record).unwrap() + "\n").unwrap();
+        let chain = SourceChain::new()
+            .then(source)
+            .then(LocalCatalog::new(dump_path));
+        assert_eq!(chain.details(&book).await.unwrap(), details);
+    }
+}
@@ -0,0 +1,509 @@
+use core::fmt;
+use sha1::{Digest, Sha1};
+use std::{collections::BTreeMap, fs, path::Path};
+
+use crate::{book::LibgenBook, identifiers::Md5};
+
+/// How deeply lists and dicts may nest, torrents need four levels
+const MAX_DEPTH: usize = 64;
+
+/// Errors from reading a `.torrent` file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TorrentError {
+    /// The file could not be read.
+    IOError(String),
+    /// The data is not valid bencode, the position is where parsing stopped.
+    BencodeError(usize),
+    /// The bencode is valid but a required torrent field is missing or has the wrong type.
+    MissingField(&'static str),
+    /// A field holds a value no torrent can have, e.g. a negative length.
+    InvalidField(&'static str),
+}
+
+impl fmt::Display for TorrentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TorrentError::IOError(err) => write!(f, "IOError: {}", err),
+            TorrentError::BencodeError(position) => write!(f, "BencodeError at {}", position),
+            TorrentError::MissingField(field) => write!(f, "MissingField: {}", field),
+            TorrentError::InvalidField(field) => write!(f, "InvalidField: {}", field),
+        }
+    }
+}
+
+impl std::error::Error for TorrentError {}
+
+/// A decoded bencode value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Bencode {
+    /// `i42e`
+    Integer(i64),
+    /// `4:spam`, not necessarily UTF-8
+    Bytes(Vec<u8>),
+    /// `l...e`
+    List(Vec<Bencode>),
+    /// `d...e`, keys are kept sorted as bencode requires
+    Dict(BTreeMap<Vec<u8>, Bencode>),
+}
+
+impl Bencode {
+    /// Decodes a complete bencoded value
+    pub fn decode(data: &[u8]) -> Result<Bencode, TorrentError> {
+        let mut decoder = Decoder {
+            data,
+            position: 0,
+            info_span: None,
+        };
+        let value = decoder.value(0)?;
+        if decoder.position != data.len() {
+            return Err(TorrentError::BencodeError(decoder.position));
+        }
+        Ok(value)
+    }
+
+    /// Encodes the value, `decode(encode(x)) == x`
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_into(&mut out);
+        out
+    }
+
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        match self {
+            Bencode::Integer(value) => out.extend(format!("i{}e", value).as_bytes()),
+            Bencode::Bytes(bytes) => {
+                out.extend(format!("{}:", bytes.len()).as_bytes());
+                out.extend(bytes);
+            }
+            Bencode::List(items) => {
+                out.push(b'l');
+                items.iter().for_each(|item| item.encode_into(out));
+                out.push(b'e');
+            }
+            Bencode::Dict(entries) => {
+                out.push(b'd');
+                for (key, value) in entries {
+                    Bencode::Bytes(key.clone()).encode_into(out);
+                    value.encode_into(out);
+                }
+                out.push(b'e');
+            }
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&Bencode> {
+        match self {
+            Bencode::Dict(entries) => entries.get(key.as_bytes()),
+            _ => None,
+        }
+    }
+
+    fn as_integer(&self) -> Option<i64> {
+        match self {
+            Bencode::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn as_string(&self) -> Option<String> {
+        match self {
+            Bencode::Bytes(bytes) => Some(String::from_utf8_lossy(bytes).into_owned()),
+            _ => None,
+        }
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    position: usize,
+    /// Byte range of the top level `info` dict, needed for the info hash
+    info_span: Option<(usize, usize)>,
+}
+
+impl Decoder<'_> {
+    fn error(&self) -> TorrentError {
+        TorrentError::BencodeError(self.position)
+    }
+
+    fn peek(&self) -> Result<u8, TorrentError> {
+        self.data.get(self.position).copied().ok_or(self.error())
+    }
+
+    fn read_until(&mut self, end: u8) -> Result<&str, TorrentError> {
+        let start = self.position;
+        let length = self.data[start..]
+            .iter()
+            .position(|b| *b == end)
+            .ok_or(self.error())?;
+        self.position += length + 1;
+        std::str::from_utf8(&self.data[start..start + length]).map_err(|_| self.error())
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Bencode, TorrentError> {
+        // Deeply nested lists would otherwise overflow the stack
+        if depth > MAX_DEPTH {
+            return Err(self.error());
+        }
+        match self.peek()? {
+            b'i' => {
+                self.position += 1;
+                let number = self.read_until(b'e')?;
+                number
+                    .parse()
+                    .map(Bencode::Integer)
+                    .map_err(|_| self.error())
+            }
+            b'l' => {
+                self.position += 1;
+                let mut items = Vec::new();
+                while self.peek()? != b'e' {
+                    items.push(self.value(depth + 1)?);
+                }
+                self.position += 1;
+                Ok(Bencode::List(items))
+            }
+            b'd' => {
+                self.position += 1;
+                let mut entries = BTreeMap::new();
+                while self.peek()? != b'e' {
+                    let Bencode::Bytes(key) = self.value(depth + 1)? else {
+                        return Err(self.error());
+                    };
+                    let start = self.position;
+                    let value = self.value(depth + 1)?;
+                    if depth == 0 && key == b"info" {
+                        self.info_span = Some((start, self.position));
+                    }
+                    entries.insert(key, value);
+                }
+                self.position += 1;
+                Ok(Bencode::Dict(entries))
+            }
+            b'0'..=b'9' => {
+                let length: usize = self.read_until(b':')?.parse().map_err(|_| self.error())?;
+                let end = self.position.checked_add(length).ok_or(self.error())?;
+                let bytes = self.data.get(self.position..end).ok_or(self.error())?;
+                self.position = end;
+                Ok(Bencode::Bytes(bytes.to_vec()))
+            }
+            _ => Err(self.error()),
+        }
+    }
+}
+
+/// A file inside a torrent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TorrentFile {
+    /// Path components below the torrents name, libgen uses the lowercase md5
+    pub path: Vec<String>,
+    /// Size in bytes
+    pub length: u64,
+    /// Where the file starts in the torrents concatenated data
+    pub offset: u64,
+}
+
+/// Where a single book lives inside a torrent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BookLocation {
+    /// Index into the torrents files, as used by `--select-file` style options
+    pub file_index: usize,
+    /// The matching file
+    pub file: TorrentFile,
+    /// First piece holding part of the book
+    pub first_piece: u64,
+    /// Last piece holding part of the book, inclusive
+    pub last_piece: u64,
+    /// Where the book starts inside the first piece
+    pub offset_in_first_piece: u64,
+}
+
+/// The parts of a `.torrent` file needed to fetch a single book
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Torrent {
+    /// The torrents name, for libgen the group directory
+    pub name: String,
+    /// Bytes per piece
+    pub piece_length: u64,
+    /// Files in torrent order
+    pub files: Vec<TorrentFile>,
+    /// The v1 info hash (BTIH)
+    pub info_hash: [u8; 20],
+}
+
+impl Torrent {
+    /// Parses a `.torrent` file from disk
+    pub fn from_file(path: &Path) -> Result<Torrent, TorrentError> {
+        let data = fs::read(path).map_err(|err| TorrentError::IOError(err.to_string()))?;
+        Torrent::from_bytes(&data)
+    }
+
+    /// Parses the contents of a `.torrent` file
+    pub fn from_bytes(data: &[u8]) -> Result<Torrent, TorrentError> {
+        let mut decoder = Decoder {
+            data,
+            position: 0,
+            info_span: None,
+        };
+        let root = decoder.value(0)?;
+        if decoder.position != data.len() {
+            return Err(TorrentError::BencodeError(decoder.position));
+        }
+        let (start, end) = decoder
+            .info_span
+            .ok_or(TorrentError::MissingField("info"))?;
+        let info_hash: [u8; 20] = Sha1::digest(&data[start..end]).into();
+
+        let info = root.get("info").ok_or(TorrentError::MissingField("info"))?;
+        let name = info
+            .get("name")
+            .and_then(Bencode::as_string)
+            .ok_or(TorrentError::MissingField("name"))?;
+        let piece_length = info
+            .get("piece length")
+            .and_then(Bencode::as_integer)
+            .ok_or(TorrentError::MissingField("piece length"))?;
+        let piece_length = u64::try_from(piece_length)
+            .ok()
+            .filter(|length| *length > 0)
+            .ok_or(TorrentError::InvalidField("piece length"))?;
+
+        let mut files = Vec::new();
+        let mut offset: u64 = 0;
+        match info.get("files") {
+            Some(Bencode::List(entries)) => {
+                for entry in entries {
+                    let length = file_length(entry)?;
+                    let path = match entry.get("path") {
+                        Some(Bencode::List(parts)) => parts
+                            .iter()
+                            .map(|part| part.as_string().ok_or(TorrentError::MissingField("path")))
+                            .collect::<Result<Vec<_>, _>>()?,
+                        _ => return Err(TorrentError::MissingField("path")),
+                    };
+                    files.push(TorrentFile {
+                        path,
+                        length,
+                        offset,
+                    });
+                    offset = offset
+                        .checked_add(length)
+                        .ok_or(TorrentError::InvalidField("length"))?;
+                }
+            }
+            // Single file torrents keep the length next to the name
+            _ => files.push(TorrentFile {
+                path: vec![name.clone()],
+                length: file_length(info)?,
+                offset: 0,
+            }),
+        }
+
+        Ok(Torrent {
+            name,
+            piece_length,
+            files,
+            info_hash,
+        })
+    }
+
+    /// The info hash as lowercase hex, as used in magnet links
+    pub fn info_hash_hex(&self) -> String {
+        self.info_hash
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// Finds the file named after an md5 and the pieces it spans
+    pub fn find_md5(&self, md5: &Md5) -> Option<BookLocation> {
+        let (file_index, file) = self.files.iter().enumerate().find(|(_, file)| {
+            file.path
+                .last()
+                .is_some_and(|name| name.eq_ignore_ascii_case(md5.as_str()))
+        })?;
+
+        let last_byte = file.offset.checked_add(file.length.saturating_sub(1))?;
+        Some(BookLocation {
+            file_index,
+            file: file.clone(),
+            first_piece: file.offset / self.piece_length,
+            last_piece: last_byte / self.piece_length,
+            offset_in_first_piece: file.offset % self.piece_length,
+        })
+    }
+}
+
+/// The `length` of a file entry, which can't be negative
+fn file_length(entry: &Bencode) -> Result<u64, TorrentError> {
+    let length = entry
+        .get("length")
+        .and_then(Bencode::as_integer)
+        .ok_or(TorrentError::MissingField("length"))?;
+    u64::try_from(length).map_err(|_| TorrentError::InvalidField("length"))
+}
+
+/// The name of the repository torrent a book is stored in, e.g. `r_3759000.torrent`
+///
+/// Libgen partitions its torrents by the same thousand-block as its download urls.
+/// Returns `None` while the books id is unknown.
+pub fn torrent_name(book: &LibgenBook) -> Option<String> {
+    let id = book.libgen_id?;
+    Some(format!("r_{:03}.torrent", id.group_id()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloader::tests::book_with_md5;
+
+    fn bytes(value: &str) -> Bencode {
+        Bencode::Bytes(value.as_bytes().to_vec())
+    }
+
+    fn dict(entries: Vec<(&str, Bencode)>) -> Bencode {
+        Bencode::Dict(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.as_bytes().to_vec(), value))
+                .collect(),
+        )
+    }
+
+    fn file(md5: &str, length: i64) -> Bencode {
+        dict(vec![
+            ("length", Bencode::Integer(length)),
+            ("path", Bencode::List(vec![bytes(md5)])),
+        ])
+    }
+
+    fn test_torrent() -> Vec<u8> {
+        dict(vec![
+            ("announce", bytes("http://tracker.invalid/announce")),
+            (
+                "info",
+                dict(vec![
+                    ("name", bytes("3000")),
+                    ("piece length", Bencode::Integer(100)),
+                    ("pieces", bytes("")),
+                    (
+                        "files",
+                        Bencode::List(vec![
+                            file("00000000000000000000000000000000", 150),
+                            file("5fa82be26689a4e6f4415ea068d35a9d", 260),
+                            file("11111111111111111111111111111111", 10),
+                        ]),
+                    ),
+                ]),
+            ),
+        ])
+        .encode()
+    }
+
+    #[test]
+    fn bencode_round_trip() {
+        let data = test_torrent();
+        assert_eq!(Bencode::decode(&data).unwrap().encode(), data);
+        assert_eq!(
+            Bencode::decode(b"i42e4:spam"),
+            Err(TorrentError::BencodeError(4))
+        );
+        assert!(Bencode::decode(b"5:spam").is_err());
+    }
+
+    #[test]
+    fn torrent_name_uses_group() {
+        let book = book_with_md5("5fa82be26689a4e6f4415ea068d35a9d");
+        assert_eq!(torrent_name(&book).as_deref(), Some("r_3000.torrent"));
+        let mut first_group = book.clone();
+        first_group.libgen_id = Some(crate::identifiers::LibgenId(531));
+        assert_eq!(torrent_name(&first_group).as_deref(), Some("r_000.torrent"));
+        first_group.libgen_id = None;
+        assert_eq!(torrent_name(&first_group), None);
+    }
+
+    #[test]
+    fn finds_book_pieces() {
+        let torrent = Torrent::from_bytes(&test_torrent()).unwrap();
+        assert_eq!(torrent.name, "3000");
+        assert_eq!(torrent.files.len(), 3);
+
+        let book = book_with_md5("5fa82be26689a4e6f4415ea068d35a9d");
+        let location = torrent.find_md5(&book.libgen_md5).unwrap();
+        assert_eq!(location.file_index, 1);
+        assert_eq!(location.file.offset, 150);
+        assert_eq!(location.first_piece, 1);
+        assert_eq!(location.last_piece, 4);
+        assert_eq!(location.offset_in_first_piece, 50);
+
+        let missing: Md5 = "22222222222222222222222222222222".parse().unwrap();
+        assert_eq!(torrent.find_md5(&missing), None);
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let mut data = vec![b'l'; 100_000];
+        data.extend(vec![b'e'; 100_000]);
+        assert!(Bencode::decode(&data).is_err());
+        assert!(Bencode::decode(b"lllleeee").is_ok());
+    }
+
+    fn torrent_with(piece_length: i64, lengths: &[i64]) -> Result<Torrent, TorrentError> {
+        let files = lengths
+            .iter()
+            .map(|length| file("5fa82be26689a4e6f4415ea068d35a9d", *length))
+            .collect();
+        let data = dict(vec![(
+            "info",
+            dict(vec![
+                ("name", bytes("3000")),
+                ("piece length", Bencode::Integer(piece_length)),
+                ("files", Bencode::List(files)),
+            ]),
+        )])
+        .encode();
+        Torrent::from_bytes(&data)
+    }
+
+    #[test]
+    fn rejects_impossible_lengths() {
+        assert_eq!(
+            torrent_with(100, &[-1]),
+            Err(TorrentError::InvalidField("length"))
+        );
+        assert_eq!(
+            torrent_with(-100, &[10]),
+            Err(TorrentError::InvalidField("piece length"))
+        );
+        assert_eq!(
+            torrent_with(100, &[i64::MAX, i64::MAX, i64::MAX]),
+            Err(TorrentError::InvalidField("length"))
+        );
+        assert!(torrent_with(100, &[10]).is_ok());
+    }
+
+    #[test]
+    fn rejects_trailing_bytes() {
+        let mut data = test_torrent();
+        let length = data.len();
+        assert!(Torrent::from_bytes(&data).is_ok());
+        data.extend_from_slice(b"garbage");
+        assert_eq!(
+            Torrent::from_bytes(&data),
+            Err(TorrentError::BencodeError(length))
+        );
+    }
+
+    #[test]
+    fn info_hash_covers_info_dict() {
+        let data = test_torrent();
+        let torrent = Torrent::from_bytes(&data).unwrap();
+        let info = Bencode::decode(&data)
+            .unwrap()
+            .get("info")
+            .unwrap()
+            .encode();
+        let expected: [u8; 20] = Sha1::digest(&info).into();
+        assert_eq!(torrent.info_hash, expected);
+        assert_eq!(torrent.info_hash_hex().len(), 40);
+    }
+}
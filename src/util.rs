@@ -1,9 +1,42 @@
+use core::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+
+use crate::{
+    book::{Author, AuthorRole},
+    identifiers::{IdentifierError, Md5},
+};
+
+/// Errors returned by [`parse_md5_from_url`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Md5UrlError {
+    /// The URL could not be parsed at all.
+    InvalidUrl(String),
+    /// Neither the query nor the path contain an MD5.
+    MissingMd5(String),
+    /// An `md5` query parameter was present but was not a valid hash.
+    InvalidMd5(IdentifierError),
+}
+
+impl fmt::Display for Md5UrlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Md5UrlError::InvalidUrl(url) => write!(f, "InvalidUrl: {}", url),
+            Md5UrlError::MissingMd5(url) => write!(f, "MissingMd5: {}", url),
+            Md5UrlError::InvalidMd5(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Md5UrlError {}
+
 /// Parses the MD5 hash from a Libgen URL.
 ///
 /// Given a Libgen URL, this function attempts to extract the MD5 hash from it.
-/// The MD5 hash is often included in Libgen URLs as a query parameter, typically
-/// following the pattern `md5=<md5_hash>`. This function extracts the hash portion
-/// and returns it in lowercase.
+/// Mirrors put the hash in one of two places, as an `md5` query parameter
+/// (`book/index.php?md5=<md5_hash>`, `ads.php?md5=<md5_hash>`) or as a path segment
+/// (`library.lol/main/<md5_hash>`, `library.bz/main/edit/<md5_hash>`). Relative links,
+/// as found on search pages, are accepted.
 ///
 /// # Arguments
 ///
@@ -11,29 +44,99 @@
 ///
 /// # Returns
 ///
-/// An `Option<String>`:
-/// - `Some(String)`: If the MD5 hash is successfully parsed from the URL, returns
-///   the hash in lowercase.
-/// - `None`: If the MD5 hash cannot be extracted from the URL or if the URL is empty.
+/// A `Result<Md5, Md5UrlError>`:
+/// - `Ok(Md5)`: The validated hash, stored in lowercase.
+/// - `Err(Md5UrlError)`: If the URL is malformed, has no MD5 or the `md5` parameter
+///   is not 32 hex characters.
 ///
 /// # Examples
 ///
 /// ```
 /// use libgen_scraper::util::parse_md5_from_url;
-/// // Note the mixed casing in the URL
-/// let url = "http://libgen.example.com/book?id=12345&md5=abCdE".to_string();
-/// let md5 = parse_md5_from_url(url);
-/// assert_eq!(md5, Some("abcde".to_string()));
+/// // Note the mixed casing and the trailing parameter
+/// let url = "http://libgen.example.com/book?md5=E75FACEC3020926608936cb68fee8066&id=1";
+/// let md5 = parse_md5_from_url(url).unwrap();
+/// assert_eq!(md5.as_str(), "e75facec3020926608936cb68fee8066");
+///
+/// let url = "http://library.lol/main/E75FACEC3020926608936CB68FEE8066";
+/// assert_eq!(parse_md5_from_url(url).unwrap(), md5);
 /// ```
 ///
 /// # Notes
 ///
-/// - If the URL contains multiple occurrences of `md5=`, this function will only
-///   consider the first occurrence.
-/// - The extracted MD5 hash is converted to lowercase before being returned.
+/// - The query parameter takes precedence over the path.
+/// - For paths, the first segment that is a valid MD5 is returned.
 ///
-pub fn parse_md5_from_url(url: String) -> Option<String> {
-    Some(url.split("md5=").nth(1)?.to_lowercase())
+pub fn parse_md5_from_url(url: &str) -> Result<Md5, Md5UrlError> {
+    // Search pages link relatively, so give them somewhere to be relative to
+    let base = Url::parse("http://libgen.invalid/").unwrap();
+    let parsed = Url::options()
+        .base_url(Some(&base))
+        .parse(url.trim())
+        .map_err(|_| Md5UrlError::InvalidUrl(url.to_owned()))?;
+
+    if let Some((_, value)) = parsed
+        .query_pairs()
+        .find(|(key, _)| key.eq_ignore_ascii_case("md5"))
+    {
+        return value.parse().map_err(Md5UrlError::InvalidMd5);
+    }
+
+    parsed
+        .path_segments()
+        .and_then(|mut segments| segments.find_map(|segment| segment.parse().ok()))
+        .ok_or_else(|| Md5UrlError::MissingMd5(url.to_owned()))
+}
+/// Parses the text of an author cell into structured authors.
+///
+/// Libgen usually links every author separately, but some entries put the whole
+/// list into a single cell separated by `;`. Each name is trimmed and a trailing
+/// role suffix such as `(auth.)` or `(ed.)` is split off when it is recognised.
+///
+/// # Arguments
+///
+/// * `raw` - The entity-decoded text of an author link or cell.
+///
+/// # Returns
+///
+/// The authors found in the text, empty names are skipped.
+///
+/// # Examples
+///
+/// ```
+/// use libgen_scraper::book::{Author, AuthorRole};
+/// use libgen_scraper::util::parse_authors;
+/// let authors = parse_authors(" Pierre A. Cholette (auth.); Jane Doe");
+/// assert_eq!(authors[0].name, "Pierre A. Cholette");
+/// assert_eq!(authors[0].role, Some(AuthorRole::Author));
+/// assert_eq!(authors[1], Author::new("Jane Doe"));
+/// ```
+///
+/// # Notes
+///
+/// - Unrecognised bracketed suffixes are left as part of the name.
+///
+pub fn parse_authors(raw: &str) -> Vec<Author> {
+    raw.split(';')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            let role = name
+                .strip_suffix(')')
+                .and_then(|rest| rest.rsplit_once('('))
+                .and_then(|(name, suffix)| {
+                    Some((name.trim_end(), AuthorRole::from_suffix(suffix)?))
+                });
+
+            match role {
+                Some((name, role)) => Author {
+                    name: name.to_owned(),
+                    role: Some(role),
+                },
+                None => Author::new(name),
+            }
+        })
+        .collect()
 }
 /// Calculates the group id for a book based on its id.
 ///
@@ -68,27 +171,69 @@ pub fn calculate_group_id(id: u64) -> u64 {
     (id / 1000) * 1000
 }
 
+/// Seconds since the unix epoch, `0` if the clock is set before it
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::util::{calculate_group_id, parse_md5_from_url};
+    use crate::book::{Author, AuthorRole};
+    use crate::util::{calculate_group_id, parse_authors, parse_md5_from_url, Md5UrlError};
 
     #[test]
     fn md5_happy_path_from_url() {
-        let url = "http://libgen.example.com/book?id=12345&md5=abcde".to_string();
+        let url = "http://libgen.example.com/book?id=12345&md5=e75facec3020926608936cb68fee8066";
         let md5 = parse_md5_from_url(url);
-        assert_eq!(md5, Some("abcde".to_string()));
+        assert_eq!(
+            md5,
+            "e75facec3020926608936cb68fee8066"
+                .parse()
+                .map_err(Md5UrlError::InvalidMd5)
+        );
     }
     #[test]
     fn md5_mixed_case_from_url() {
-        let url = "http://libgen.example.com/book?id=12345&md5=123CbbDeeeFGS".to_string();
+        let url = "http://libgen.example.com/book?id=12345&md5=E75FACEC3020926608936cb68fee8066";
+        let md5 = parse_md5_from_url(url).unwrap();
+        assert_eq!(md5.as_str(), "e75facec3020926608936cb68fee8066");
+    }
+    #[test]
+    fn md5_trailing_params_and_fragment_from_url() {
+        let url = "http://libgen.li/ads.php?md5=E75FACEC3020926608936CB68FEE8066&foo=1#top";
+        let md5 = parse_md5_from_url(url).unwrap();
+        assert_eq!(md5.as_str(), "e75facec3020926608936cb68fee8066");
+    }
+    #[test]
+    fn md5_relative_from_url() {
+        let url = "book/index.php?md5=E75FACEC3020926608936CB68FEE8066";
+        assert!(parse_md5_from_url(url).is_ok());
+    }
+    #[test]
+    fn md5_path_segment_from_url() {
+        for url in [
+            "http://library.lol/main/E75FACEC3020926608936CB68FEE8066",
+            "https://library.bz/main/edit/E75FACEC3020926608936CB68FEE8066",
+            "https://download.library.lol/main/12000/e75facec3020926608936cb68fee8066/Title.djvu",
+        ] {
+            let md5 = parse_md5_from_url(url).unwrap();
+            assert_eq!(md5.as_str(), "e75facec3020926608936cb68fee8066");
+        }
+    }
+    #[test]
+    fn md5_invalid_from_url() {
+        let url = "http://libgen.example.com/book?id=12345&md5=123CbbDeeeFGS";
         let md5 = parse_md5_from_url(url);
-        assert_eq!(md5, Some("123cbbdeeefgs".to_string()));
+        assert!(matches!(md5, Err(Md5UrlError::InvalidMd5(_))));
     }
     #[test]
     fn md5_missing_from_url() {
-        let url = "http://libgen.example.com/book?id=12345".to_string();
+        let url = "http://libgen.example.com/book?id=12345";
         let md5 = parse_md5_from_url(url);
-        assert_eq!(md5, None);
+        assert_eq!(md5, Err(Md5UrlError::MissingMd5(url.to_string())));
     }
 
     #[test]
@@ -115,4 +260,30 @@ mod tests {
     fn group_id_large() {
         assert_eq!(calculate_group_id(19992123), 19992000);
     }
+
+    #[test]
+    fn authors_trimmed_with_role() {
+        let authors = parse_authors(" Pierre A. Cholette (auth.)");
+        assert_eq!(
+            authors,
+            vec![Author {
+                name: "Pierre A. Cholette".to_string(),
+                role: Some(AuthorRole::Author)
+            }]
+        );
+    }
+
+    #[test]
+    fn authors_split_single_cell() {
+        let authors = parse_authors("Jiri Adamek; Horst Herrlich (ed.);; ");
+        assert_eq!(authors.len(), 2);
+        assert_eq!(authors[0], Author::new("Jiri Adamek"));
+        assert_eq!(authors[1].role, Some(AuthorRole::Editor));
+    }
+
+    #[test]
+    fn authors_unknown_suffix_kept() {
+        let authors = parse_authors("Martin Luther King (Jr)");
+        assert_eq!(authors, vec![Author::new("Martin Luther King (Jr)")]);
+    }
 }
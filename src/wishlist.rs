@@ -0,0 +1,462 @@
+use core::fmt;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{fs, sync::mpsc};
+
+use crate::{
+    book::LibgenBook,
+    identifiers::{Isbn, Md5},
+    parser::SearchColumn,
+    queue::{write_atomically, DownloadQueue},
+    scraper::LibgenClient,
+    util::unix_now,
+};
+
+/// Errors from keeping the wishlist file or queueing its finds
+#[derive(Debug)]
+pub enum WishlistError {
+    /// The wishlist file could not be read or written.
+    IOError(String),
+    /// The wishlist file is not a valid wishlist.
+    ParsingError(String),
+    /// A find could not be added to the download queue.
+    QueueError(String),
+}
+
+impl fmt::Display for WishlistError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WishlistError::IOError(err) => write!(f, "IOError: {}", err),
+            WishlistError::ParsingError(err) => write!(f, "ParsingError: {}", err),
+            WishlistError::QueueError(err) => write!(f, "QueueError: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for WishlistError {}
+
+impl From<std::io::Error> for WishlistError {
+    fn from(error: std::io::Error) -> Self {
+        WishlistError::IOError(error.to_string())
+    }
+}
+
+impl From<serde_json::Error> for WishlistError {
+    fn from(error: serde_json::Error) -> Self {
+        WishlistError::ParsingError(error.to_string())
+    }
+}
+
+/// What a wish is looking for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WishQuery {
+    /// A title, matched from its start like [`LibgenClient::search_book_by_title`]
+    Title(String),
+    /// Any book by this author
+    Author(String),
+    /// The book with this ISBN, in either form
+    Isbn(Isbn),
+}
+
+impl WishQuery {
+    fn search(&self) -> (SearchColumn, String) {
+        match self {
+            WishQuery::Title(title) => (SearchColumn::Title, title.clone()),
+            WishQuery::Author(author) => (SearchColumn::Author, author.clone()),
+            WishQuery::Isbn(isbn) => (SearchColumn::Isbn, isbn.to_string()),
+        }
+    }
+
+    /// Whether a search result really is what was wished for
+    pub fn matches(&self, book: &LibgenBook) -> bool {
+        match self {
+            WishQuery::Title(title) => book
+                .title
+                .trim()
+                .to_lowercase()
+                .starts_with(&title.trim().to_lowercase()),
+            WishQuery::Author(author) => {
+                let author = author.trim().to_lowercase();
+                book.authors
+                    .iter()
+                    .any(|credited| credited.name.to_lowercase().contains(&author))
+            }
+            WishQuery::Isbn(isbn) => book
+                .isbns
+                .iter()
+                .any(|listed| listed.to_isbn13() == isbn.to_isbn13()),
+        }
+    }
+}
+
+impl fmt::Display for WishQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WishQuery::Title(title) => write!(f, "title \"{}\"", title),
+            WishQuery::Author(author) => write!(f, "author \"{}\"", author),
+            WishQuery::Isbn(isbn) => write!(f, "ISBN {}", isbn),
+        }
+    }
+}
+
+/// Which of several matching books to take
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SelectionPolicy {
+    /// The first match, libgen lists the newest first
+    #[default]
+    First,
+    /// The most recently published match
+    Newest,
+    /// The first match in the earliest listed format, e.g. `["epub", "pdf"]`
+    ///
+    /// Matches in other formats are not taken.
+    PreferFormats(Vec<String>),
+}
+
+impl SelectionPolicy {
+    /// Picks a book from the matches, in search order
+    pub fn select<'a>(&self, matches: &'a [LibgenBook]) -> Option<&'a LibgenBook> {
+        match self {
+            SelectionPolicy::First => matches.first(),
+            // max_by_key keeps the last maximum, search order should win ties
+            SelectionPolicy::Newest => matches
+                .iter()
+                .rev()
+                .max_by_key(|book| book.year.unwrap_or(0)),
+            SelectionPolicy::PreferFormats(formats) => formats.iter().find_map(|format| {
+                matches
+                    .iter()
+                    .find(|book| book.file_type.eq_ignore_ascii_case(format))
+            }),
+        }
+    }
+}
+
+/// A saved query
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Wish {
+    /// What is looked for
+    pub query: WishQuery,
+    /// Which match is taken
+    pub policy: SelectionPolicy,
+    /// The book taken, once one turned up
+    pub fulfilled_by: Option<Md5>,
+    /// When the query last ran, in seconds since the unix epoch
+    pub last_checked: Option<u64>,
+}
+
+/// Something the watcher noticed
+#[derive(Debug, Clone, PartialEq)]
+pub enum WishlistEvent {
+    /// A wished for book appeared.
+    Found {
+        /// The wish it fulfills
+        query: WishQuery,
+        /// The book selected
+        book: LibgenBook,
+        /// Whether it was added to the download queue
+        enqueued: bool,
+    },
+    /// A query could not be run, it is tried again next time.
+    SearchFailed {
+        /// The query
+        query: WishQuery,
+        /// What went wrong
+        error: String,
+    },
+}
+
+/// How often and how politely wishes are checked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WishlistConfig {
+    /// Minimum time before a wish is searched again
+    pub recheck_interval: Duration,
+    /// Pause between two searches
+    pub query_delay: Duration,
+}
+
+impl Default for WishlistConfig {
+    fn default() -> Self {
+        WishlistConfig {
+            recheck_interval: Duration::from_secs(6 * 60 * 60),
+            query_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct WishlistState {
+    wishes: Vec<Wish>,
+    /// Every book taken so far, so two wishes never fetch the same file
+    fetched: BTreeSet<Md5>,
+}
+
+/// Saved queries that are re-run until a matching book shows up on libgen
+pub struct Wishlist {
+    path: PathBuf,
+    config: WishlistConfig,
+    state: WishlistState,
+    queue: Option<DownloadQueue>,
+}
+
+impl Wishlist {
+    /// Opens the wishlist at `path`, empty when the file doesn't exist yet
+    pub async fn load(
+        path: impl AsRef<Path>,
+        config: WishlistConfig,
+    ) -> Result<Wishlist, WishlistError> {
+        let path = path.as_ref().to_path_buf();
+        let state = if fs::try_exists(&path).await? {
+            serde_json::from_slice(&fs::read(&path).await?)?
+        } else {
+            WishlistState::default()
+        };
+        Ok(Wishlist {
+            path,
+            config,
+            state,
+            queue: None,
+        })
+    }
+
+    /// Adds finds to this queue instead of only reporting them
+    pub fn enqueue_into(&mut self, queue: DownloadQueue) {
+        self.queue = Some(queue);
+    }
+
+    /// The saved wishes
+    pub fn wishes(&self) -> &[Wish] {
+        &self.state.wishes
+    }
+
+    /// Whether a book was already taken for some wish
+    pub fn was_fetched(&self, md5: &Md5) -> bool {
+        self.state.fetched.contains(md5)
+    }
+
+    /// Saves a query, returning `false` if it was already on the list
+    pub async fn add(
+        &mut self,
+        query: WishQuery,
+        policy: SelectionPolicy,
+    ) -> Result<bool, WishlistError> {
+        if self.state.wishes.iter().any(|wish| wish.query == query) {
+            return Ok(false);
+        }
+        self.state.wishes.push(Wish {
+            query,
+            policy,
+            fulfilled_by: None,
+            last_checked: None,
+        });
+        self.save().await?;
+        Ok(true)
+    }
+
+    /// Forgets a query, returning `false` if it wasn't on the list
+    pub async fn remove(&mut self, query: &WishQuery) -> Result<bool, WishlistError> {
+        let before = self.state.wishes.len();
+        self.state.wishes.retain(|wish| &wish.query != query);
+        if self.state.wishes.len() == before {
+            return Ok(false);
+        }
+        self.save().await?;
+        Ok(true)
+    }
+
+    /// Runs every open wish that is due, waiting between searches
+    ///
+    /// Matches already fetched for another wish are passed over. The list is saved
+    /// after each wish, so an interrupted check loses nothing.
+    pub async fn check(
+        &mut self,
+        client: &LibgenClient,
+    ) -> Result<Vec<WishlistEvent>, WishlistError> {
+        let mut events = Vec::new();
+        let mut searched = false;
+
+        for index in 0..self.state.wishes.len() {
+            let wish = &self.state.wishes[index];
+            let now = unix_now();
+            let due = wish.last_checked.is_none_or(|checked| {
+                now.saturating_sub(checked) >= self.config.recheck_interval.as_secs()
+            });
+            if wish.fulfilled_by.is_some() || !due {
+                continue;
+            }
+
+            // We need to be gentlemen and not spam libgen
+            if searched {
+                tokio::time::sleep(self.config.query_delay).await;
+            }
+            searched = true;
+
+            let query = wish.query.clone();
+            let (column, text) = query.search();
+            // A failed search leaves the wish due, so the next check tries again
+            match client.search(column, &text).await {
+                Ok(results) => {
+                    self.state.wishes[index].last_checked = Some(unix_now());
+                    let matches: Vec<LibgenBook> = results
+                        .into_iter()
+                        .filter(|book| query.matches(book) && !self.was_fetched(&book.libgen_md5))
+                        .collect();
+                    if let Some(book) = self.state.wishes[index].policy.select(&matches) {
+                        let book = book.clone();
+                        let enqueued = match &self.queue {
+                            Some(queue) => queue
+                                .push(book.clone())
+                                .await
+                                .map_err(|err| WishlistError::QueueError(err.to_string()))?,
+                            None => false,
+                        };
+                        self.state.fetched.insert(book.libgen_md5.clone());
+                        self.state.wishes[index].fulfilled_by = Some(book.libgen_md5.clone());
+                        events.push(WishlistEvent::Found {
+                            query,
+                            book,
+                            enqueued,
+                        });
+                    }
+                }
+                Err(err) => events.push(WishlistEvent::SearchFailed {
+                    query,
+                    error: err.to_string(),
+                }),
+            }
+            self.save().await?;
+        }
+        Ok(events)
+    }
+
+    /// Checks the wishlist forever, sending what it notices to `events`
+    ///
+    /// Returns once the receiving side is dropped.
+    pub async fn watch(
+        &mut self,
+        client: &LibgenClient,
+        events: mpsc::Sender<WishlistEvent>,
+    ) -> Result<(), WishlistError> {
+        loop {
+            for event in self.check(client).await? {
+                if events.send(event).await.is_err() {
+                    return Ok(());
+                }
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(self.config.query_delay.max(Duration::from_secs(60))) => {}
+                _ = events.closed() => return Ok(()),
+            }
+        }
+    }
+
+    async fn save(&self) -> Result<(), WishlistError> {
+        Ok(write_atomically(&self.path, &serde_json::to_vec_pretty(&self.state)?).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloader::tests::{book_with_md5, serve_bytes, test_client};
+
+    #[test]
+    fn policies_select() {
+        let mut pdf = book_with_md5("5eb63bbbe01eeed093cb22bb8f5acdc3");
+        pdf.year = Some(1990);
+        let mut epub = book_with_md5("5fa82be26689a4e6f4415ea068d35a9d");
+        epub.file_type = "epub".to_owned();
+        epub.year = Some(2004);
+        let matches = vec![pdf.clone(), epub.clone()];
+
+        assert_eq!(SelectionPolicy::First.select(&matches), Some(&pdf));
+        assert_eq!(SelectionPolicy::Newest.select(&matches), Some(&epub));
+        assert_eq!(
+            SelectionPolicy::PreferFormats(vec!["EPUB".to_owned(), "pdf".to_owned()])
+                .select(&matches),
+            Some(&epub)
+        );
+        assert_eq!(
+            SelectionPolicy::PreferFormats(vec!["djvu".to_owned()]).select(&matches),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn fulfilled_wishes_are_remembered() {
+        let host = serve_bytes(include_bytes!("../benches/benchmark_page.htm")).await;
+        let client = test_client(&host).build().unwrap();
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("wishlist.json");
+        let config = WishlistConfig {
+            query_delay: Duration::ZERO,
+            ..WishlistConfig::default()
+        };
+
+        let mut wishlist = Wishlist::load(&path, config.clone()).await.unwrap();
+        let query = WishQuery::Title("Performance Evaluation".to_owned());
+        assert!(wishlist
+            .add(query.clone(), SelectionPolicy::First)
+            .await
+            .unwrap());
+        assert!(!wishlist.add(query, SelectionPolicy::Newest).await.unwrap());
+        wishlist
+            .add(
+                WishQuery::Author("Nobody In Particular".to_owned()),
+                SelectionPolicy::First,
+            )
+            .await
+            .unwrap();
+
+        let events = wishlist.check(&client).await.unwrap();
+        assert_eq!(events.len(), 1);
+        let WishlistEvent::Found { book, enqueued, .. } = &events[0] else {
+            panic!("expected a find, got {:?}", events[0]);
+        };
+        assert!(!enqueued);
+
+        // Reloaded, the find is remembered and the other wish isn't due yet
+        let mut wishlist = Wishlist::load(&path, config).await.unwrap();
+        assert!(wishlist.was_fetched(&book.libgen_md5));
+        assert!(wishlist.check(&client).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_searches_are_retried() {
+        // Nothing listens on a port that was just closed
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let client = test_client(&format!("http://{}", address)).build().unwrap();
+        let directory = tempfile::tempdir().unwrap();
+        let mut wishlist = Wishlist::load(
+            directory.path().join("wishlist.json"),
+            WishlistConfig {
+                query_delay: Duration::ZERO,
+                ..WishlistConfig::default()
+            },
+        )
+        .await
+        .unwrap();
+        wishlist
+            .add(
+                WishQuery::Title("Performance Evaluation".to_owned()),
+                SelectionPolicy::First,
+            )
+            .await
+            .unwrap();
+
+        for _ in 0..2 {
+            let events = wishlist.check(&client).await.unwrap();
+            assert!(matches!(
+                events.as_slice(),
+                [WishlistEvent::SearchFailed { .. }]
+            ));
+        }
+    }
+}
@@ -1,7 +1,4 @@
-use libgen_scraper::{
-    book::LibgenBook,
-    scraper::{self, LibgenClient},
-};
+use libgen_scraper::scraper::LibgenClient;
 
 #[test]
 fn download_book() {
@@ -17,17 +14,18 @@ fn download_book() {
     //         authors: vec!["José Manuel Ortega".to_string()],
     //         publisher: "Packt Publishing".to_owned(),
     //     };
+    let runtime = tokio::runtime::Runtime::new().unwrap();
     let result = test_client.search_book_by_title(&generic_book);
 
-    match tokio::runtime::Runtime::new().unwrap().block_on(result) {
+    match runtime.block_on(result) {
         Ok(actual_result) => {
             // Assert equality
             match actual_result {
                 Some(result) => {
                     //result.download().is_ok();
-                    match test_client.download_book(&result) {
+                    match runtime.block_on(test_client.download_book(&result)) {
+                        Ok(file) => assert_eq!(file.md5, result.libgen_md5),
                         Err(err) => panic!("{}", err),
-                        Ok(res) => assert!(true),
                     }
                     // assert!(result.download().is_ok());
                 }